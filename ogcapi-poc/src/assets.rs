@@ -0,0 +1,464 @@
+use anyhow::Context;
+use axum::{
+    async_trait,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use sqlx::PgPool;
+use url::Url;
+
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::{
+    common::Crs,
+    processes::{Execute, Process},
+    stac::Asset,
+};
+
+use crate::{
+    catalog::{self, Update},
+    visibility, AWS_S3_BUCKET, AWS_S3_BUCKET_BASE,
+};
+
+/// STAC Asset remover
+pub(crate) struct AssetDeleter;
+
+/// STAC Asset mover
+pub(crate) struct AssetMover;
+
+/// Asset location
+#[derive(Deserialize, Debug, JsonSchema)]
+struct Target {
+    /// Collection `id`
+    collection: String,
+    /// Item `id`, the asset belongs to the collection if not set
+    item: Option<String>,
+    /// Asset `id`
+    id: String,
+}
+
+/// Asset delete input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct AssetDeleterInputs {
+    /// Asset to delete
+    #[serde(flatten)]
+    asset: Target,
+}
+
+/// Asset move input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct AssetMoverInputs {
+    /// Asset to move
+    #[serde(flatten)]
+    asset: Target,
+    /// New asset location
+    target: Target,
+    /// New S3 key, the object is kept in place if not set
+    key: Option<String>,
+}
+
+/// Asset delete/move output schema
+#[derive(Serialize, JsonSchema)]
+struct AssetOutputs {
+    /// Href of the asset
+    href: String,
+    /// Whether the backing S3 object was deleted
+    #[serde(rename = "objectDeleted")]
+    object_deleted: bool,
+    /// Assets still referencing the S3 object
+    #[serde(rename = "referencedBy", skip_serializing_if = "Vec::is_empty")]
+    referenced_by: Vec<String>,
+}
+
+fn process<I: JsonSchema>(id: String) -> Process {
+    // Config schema generation
+    let settings = SchemaSettings::default().with(|s| {
+        s.option_nullable = false;
+        s.option_add_null_type = false;
+        s.inline_subschemas = true;
+    });
+    let gen = settings.into_generator();
+
+    Process::new(
+        id,
+        "0.1.0",
+        &serde_json::to_value(&gen.clone().into_root_schema_for::<I>().schema).unwrap(),
+        &serde_json::to_value(&gen.into_root_schema_for::<AssetOutputs>().schema).unwrap(),
+    )
+}
+
+#[async_trait]
+impl Processor for AssetDeleter {
+    fn id(&self) -> String {
+        "delete-asset".to_string()
+    }
+    fn process(&self) -> Process {
+        process::<AssetDeleterInputs>(self.id())
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: AssetDeleterInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let target = &inputs.asset;
+        let asset: Asset = match catalog::asset(
+            &target.collection,
+            target.item.as_deref(),
+            &target.id,
+            &state.db.pool,
+        )
+        .await?
+        {
+            Some(asset) => serde_json::from_value(asset).context("Failed to parse asset")?,
+            None => {
+                return Err(Error::Exception(
+                    StatusCode::NOT_FOUND,
+                    format!("Asset `{}` not found", target.id),
+                ))
+            }
+        };
+
+        // Delete the object first, a failure leaves the catalog untouched
+        let outputs = delete_object(&asset.href, Some(&path(target)), state).await?;
+
+        if let Err(e) = remove_asset(target, state).await {
+            if !outputs.object_deleted {
+                return Err(e);
+            }
+            return Err(Error::Exception(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!(
+                    "Object of asset `{}` deleted, but removing the asset failed: {e}",
+                    target.id
+                ),
+            ));
+        }
+
+        Ok(Json(outputs).into_response())
+    }
+}
+
+#[async_trait]
+impl Processor for AssetMover {
+    fn id(&self) -> String {
+        "move-asset".to_string()
+    }
+    fn process(&self) -> Process {
+        process::<AssetMoverInputs>(self.id())
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: AssetMoverInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        // Ensure target exists before touching the asset
+        exists(&inputs.target, state).await?;
+
+        let pool = &state.db.pool;
+        let source = &inputs.asset;
+        let target = &inputs.target;
+
+        let mut asset: Asset =
+            match catalog::asset(&source.collection, source.item.as_deref(), &source.id, pool)
+                .await?
+            {
+                Some(asset) => serde_json::from_value(asset).context("Failed to parse asset")?,
+                None => {
+                    return Err(Error::Exception(
+                        StatusCode::NOT_FOUND,
+                        format!("Asset `{}` not found", source.id),
+                    ))
+                }
+            };
+        let href = asset.href.to_owned();
+        let previous =
+            catalog::asset(&target.collection, target.item.as_deref(), &target.id, pool).await?;
+
+        // Copy S3 object
+        let mut copied = None;
+        if let Some(key) = &inputs.key {
+            let source_key = s3_key(&href).ok_or_else(|| {
+                Error::Exception(
+                    StatusCode::BAD_REQUEST,
+                    format!("Asset `{}` is not stored on S3", source.id),
+                )
+            })?;
+            let key = key.trim_start_matches('/');
+
+            if source_key != key {
+                state
+                    .s3
+                    .client
+                    .copy_object()
+                    .copy_source(format!("{AWS_S3_BUCKET}/{source_key}"))
+                    .bucket(AWS_S3_BUCKET)
+                    .key(key)
+                    .acl(visibility::acl(&target.collection, pool).await?)
+                    .send()
+                    .await
+                    .context("Failed to copy S3 object")?;
+
+                asset.href = format!("{AWS_S3_BUCKET_BASE}/{key}");
+                copied = Some(key.to_owned());
+            }
+        }
+
+        // Objects kept in place get the visibility of the target collection
+        let mut acl_changed = None;
+        if copied.is_none() && source.collection != target.collection {
+            if let Some(key) = s3_key(&href) {
+                put_acl(key, &target.collection, state).await?;
+                acl_changed = Some(key);
+            }
+        }
+
+        // Update catalog, compensating the completed steps on failure
+        let moved: Result<()> = async {
+            insert_asset(target, asset.clone(), state).await?;
+            if !same_location(source, target) {
+                remove_asset(source, state).await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = moved {
+            let restored = match previous {
+                Some(previous) => {
+                    let previous =
+                        serde_json::from_value(previous).context("Failed to parse asset")?;
+                    insert_asset(target, previous, state).await
+                }
+                None if !same_location(source, target) => {
+                    remove_asset(target, state).await.map(|_| ())
+                }
+                None => Ok(()),
+            };
+            if let Err(e) = restored {
+                tracing::error!("failed to restore asset `{}`: {e}", target.id);
+            }
+            if let Some(key) = &copied {
+                if let Err(e) = state.s3.delete_object(AWS_S3_BUCKET, key).await {
+                    tracing::warn!("failed to remove object `{key}`: {e}");
+                }
+            }
+            if let Some(key) = acl_changed {
+                if let Err(e) = put_acl(key, &source.collection, state).await {
+                    tracing::error!("failed to restore acl of object `{key}`: {e}");
+                }
+            }
+            return Err(e);
+        }
+
+        // Delete the previous S3 object once the catalog points to the copy
+        let outputs = match copied {
+            Some(_) => {
+                let mut outputs = match delete_object(&href, None, state).await {
+                    Ok(outputs) => outputs,
+                    Err(e) => {
+                        tracing::warn!("failed to delete object of `{href}`: {e}");
+                        AssetOutputs {
+                            href: href.to_owned(),
+                            object_deleted: false,
+                            referenced_by: Vec::new(),
+                        }
+                    }
+                };
+                outputs.href = asset.href;
+                outputs
+            }
+            None => AssetOutputs {
+                href,
+                object_deleted: false,
+                referenced_by: Vec::new(),
+            },
+        };
+
+        Ok(Json(outputs).into_response())
+    }
+}
+
+/// Whether two targets point to the same asset
+fn same_location(a: &Target, b: &Target) -> bool {
+    a.collection == b.collection && a.item == b.item && a.id == b.id
+}
+
+/// Path of the asset, as listed in `referencedBy`
+fn path(target: &Target) -> String {
+    match &target.item {
+        Some(item) => format!(
+            "collections/{}/items/{item}/assets/{}",
+            target.collection, target.id
+        ),
+        None => format!("collections/{}/assets/{}", target.collection, target.id),
+    }
+}
+
+/// Set the ACL of an S3 object according to the visibility of `collection`
+async fn put_acl(key: &str, collection: &str, state: &State) -> Result<()> {
+    state
+        .s3
+        .client
+        .put_object_acl()
+        .bucket(AWS_S3_BUCKET)
+        .key(key)
+        .acl(visibility::acl(collection, &state.db.pool).await?)
+        .send()
+        .await
+        .context("Failed to update S3 object acl")?;
+
+    Ok(())
+}
+
+/// Check that the item/collection of the target exists
+async fn exists(target: &Target, state: &State) -> Result<()> {
+    let exists = if let Some(item_id) = &target.item {
+        state
+            .drivers
+            .features
+            .read_feature(&target.collection, item_id, &Crs::default())
+            .await?
+            .is_some()
+    } else {
+        state
+            .drivers
+            .collections
+            .read_collection(&target.collection)
+            .await?
+            .is_some()
+    };
+
+    if exists {
+        Ok(())
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+/// Remove asset from item/collection
+async fn remove_asset(target: &Target, state: &State) -> Result<Asset> {
+    let pool = &state.db.pool;
+
+    let asset = match &target.item {
+        Some(item_id) => {
+            catalog::remove_item_asset(&target.collection, item_id, &target.id, pool).await?
+        }
+        None => catalog::remove_collection_asset(&target.collection, &target.id, pool).await?,
+    };
+
+    match asset {
+        Some(asset) => Ok(serde_json::from_value(asset).context("Failed to parse asset")?),
+        None => Err(Error::Exception(
+            StatusCode::NOT_FOUND,
+            format!("Asset `{}` not found", target.id),
+        )),
+    }
+}
+
+/// Insert asset into item/collection
+async fn insert_asset(target: &Target, asset: Asset, state: &State) -> Result<()> {
+    let pool = &state.db.pool;
+    let assets = Map::from_iter([(target.id.to_owned(), serde_json::to_value(asset).unwrap())]);
+
+    let found = match &target.item {
+        Some(item_id) => matches!(
+            catalog::merge_item(&target.collection, item_id, assets, None, None, pool).await?,
+            Update::Updated
+        ),
+        None => catalog::merge_collection(&target.collection, assets, pool).await?,
+    };
+
+    if found {
+        Ok(())
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+/// Delete the S3 object behind `href` unless other assets than `ignore` reference it
+async fn delete_object(href: &str, ignore: Option<&str>, state: &State) -> Result<AssetOutputs> {
+    let mut outputs = AssetOutputs {
+        href: href.to_owned(),
+        object_deleted: false,
+        referenced_by: Vec::new(),
+    };
+
+    if let Some(key) = s3_key(href) {
+        outputs.referenced_by = referenced_by(href, &state.db.pool).await?;
+        outputs
+            .referenced_by
+            .retain(|path| Some(path.as_str()) != ignore);
+
+        if outputs.referenced_by.is_empty() {
+            state.s3.delete_object(AWS_S3_BUCKET, key).await?;
+            outputs.object_deleted = true;
+        } else {
+            tracing::info!(
+                "keep object `{key}` referenced by {}",
+                outputs.referenced_by.join(", ")
+            );
+        }
+    }
+
+    Ok(outputs)
+}
+
+/// S3 key of assets residing on the bucket
+pub(crate) fn s3_key(href: &str) -> Option<&str> {
+    href.strip_prefix(AWS_S3_BUCKET_BASE)
+        .and_then(|key| key.strip_prefix('/'))
+}
+
+/// S3 key of assets residing under the prefix of `collection`
+pub(crate) fn collection_key<'a>(href: &'a str, collection: &str) -> Option<&'a str> {
+    s3_key(href).filter(|key| {
+        key.strip_prefix(collection)
+            .map_or(false, |rest| rest.starts_with('/'))
+    })
+}
+
+/// Assets of all collections and items pointing to `href`
+async fn referenced_by(href: &str, pool: &PgPool) -> anyhow::Result<Vec<String>> {
+    let mut assets: Vec<String> = sqlx::query_as(
+        r#"
+        SELECT c.id, a.key FROM meta.collections c, jsonb_each(c.collection->'assets') a
+        WHERE a.value->>'href' = $1
+        "#,
+    )
+    .bind(href)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(id, asset): (String, String)| format!("collections/{id}/assets/{asset}"))
+    .collect();
+
+    let collections: Vec<String> = sqlx::query_scalar("SELECT id FROM meta.collections")
+        .fetch_all(pool)
+        .await?;
+
+    for collection in collections {
+        let items: Vec<(String, String)> = sqlx::query_as(&format!(
+            r#"
+            SELECT i.id, a.key FROM {} i, jsonb_each(i.assets) a
+            WHERE a.value->>'href' = $1
+            "#,
+            catalog::items_table(&collection)
+        ))
+        .bind(href)
+        .fetch_all(pool)
+        .await?;
+
+        assets.extend(
+            items
+                .into_iter()
+                .map(|(id, asset)| format!("collections/{collection}/items/{id}/assets/{asset}")),
+        );
+    }
+
+    Ok(assets)
+}
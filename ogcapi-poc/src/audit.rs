@@ -0,0 +1,485 @@
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{header::CONTENT_LENGTH, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::{types::Json as Jsonb, PgPool};
+use uuid::Uuid;
+
+use ogcapi_services::{Error, Result};
+
+use crate::{auth::User, catalog, grants};
+
+/// Request id header, generated if not set by the client or proxy
+static REQUEST_ID: &str = "x-request-id";
+
+/// Maximum length of request ids set by clients
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Default and maximum number of returned entries
+const LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Create the audit log table
+pub(crate) async fn setup(pool: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS meta.audit_log (
+            id bigserial PRIMARY KEY,
+            time timestamptz NOT NULL DEFAULT now(),
+            principal text,
+            method text NOT NULL,
+            path text NOT NULL,
+            collection text,
+            item text,
+            status integer NOT NULL,
+            request_id text NOT NULL,
+            summary jsonb NOT NULL DEFAULT '{}'::jsonb
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS audit_log_time ON meta.audit_log (time)")
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Audit log entry
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub(crate) struct Entry {
+    id: i64,
+    time: DateTime<Utc>,
+    principal: Option<String>,
+    method: String,
+    path: String,
+    collection: Option<String>,
+    item: Option<String>,
+    status: i32,
+    request_id: String,
+    summary: Jsonb<Value>,
+}
+
+/// String input value, either plain or wrapped as `{"value": ...}`
+fn input<'a>(inputs: &'a Value, key: &str) -> Option<&'a str> {
+    grants::input(inputs, key)?.as_str()
+}
+
+/// Item `id` of an input, either the `id` itself or an item object
+fn item_id(inputs: &Value) -> Option<String> {
+    let item = grants::input(inputs, "item")?;
+    item.as_str()
+        .or_else(|| item.get("id")?.as_str())
+        .map(ToOwned::to_owned)
+}
+
+/// Collection, item and asset of an input
+fn location(inputs: &Value) -> Value {
+    json!({
+        "collection": input(inputs, "collection"),
+        "item": item_id(inputs),
+        "asset": input(inputs, "id"),
+        "key": input(inputs, "key"),
+    })
+}
+
+/// Target collection/item and a summary of the change
+fn describe(
+    method: &Method,
+    segments: &[&str],
+    body: &Value,
+) -> (Option<String>, Option<String>, Value) {
+    let keys = |value: &Value| -> Vec<String> {
+        value
+            .as_object()
+            .map(|object| object.keys().cloned().collect())
+            .unwrap_or_default()
+    };
+    let action = match *method {
+        Method::POST => "create",
+        Method::PUT | Method::PATCH => "update",
+        Method::DELETE => "delete",
+        _ => "other",
+    };
+
+    match segments {
+        ["processes", process, "execution"] => {
+            let inputs = body.get("inputs").cloned().unwrap_or_default();
+            let mut summary = json!({
+                "process": process,
+                "asset": input(&inputs, "id").or_else(|| input(&inputs, "asset")),
+                "key": input(&inputs, "key"),
+            });
+            if let Some(target) = grants::input(&inputs, "target") {
+                summary["target"] = location(target);
+            }
+            if let Some(files) = grants::input(&inputs, "files").and_then(Value::as_array) {
+                summary["files"] = files.iter().map(location).collect();
+            }
+            (
+                input(&inputs, "collection").map(ToOwned::to_owned),
+                item_id(&inputs),
+                summary,
+            )
+        }
+        ["collections", collection, "items", item, ..] => (
+            Some(collection.to_string()),
+            Some(item.to_string()),
+            json!({ "action": action, "fields": keys(body), "assets": keys(&body["assets"]) }),
+        ),
+        ["collections", collection, "items"] => (
+            Some(collection.to_string()),
+            body.get("id")
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned),
+            json!({ "action": action, "fields": keys(body), "assets": keys(&body["assets"]) }),
+        ),
+        ["collections", collection, ..] => (
+            Some(collection.to_string()),
+            None,
+            json!({ "action": action, "fields": keys(body), "assets": keys(&body["assets"]) }),
+        ),
+        ["collections"] => (
+            body.get("id")
+                .and_then(Value::as_str)
+                .map(ToOwned::to_owned),
+            None,
+            json!({ "action": action, "fields": keys(body) }),
+        ),
+        _ => (None, None, json!({ "action": action })),
+    }
+}
+
+/// Properties and assets of an item, or of a collection if no item is given,
+/// `null` if it does not exist
+async fn snapshot(collection: &str, item: Option<&str>, pool: &PgPool) -> anyhow::Result<Value> {
+    let snapshot = match item {
+        Some(item) => {
+            let row: Option<(Option<Jsonb<Value>>, Option<Jsonb<Value>>)> =
+                sqlx::query_as(&format!(
+                    "SELECT properties, assets FROM {} WHERE id = $1",
+                    catalog::items_table(collection)
+                ))
+                .bind(item)
+                .fetch_optional(pool)
+                .await?;
+
+            row.map(|(properties, assets)| {
+                json!({
+                    "properties": properties.map(|p| p.0),
+                    "assets": assets.map(|a| a.0),
+                })
+            })
+        }
+        None => {
+            let row: Option<Jsonb<Value>> =
+                sqlx::query_scalar("SELECT collection FROM meta.collections WHERE id = $1")
+                    .bind(collection)
+                    .fetch_optional(pool)
+                    .await?;
+
+            // Collection members other than assets and links are its properties
+            row.map(|collection| {
+                let mut properties = collection.0.as_object().cloned().unwrap_or_default();
+                let assets = properties.remove("assets");
+                properties.remove("links");
+                json!({ "properties": properties, "assets": assets })
+            })
+        }
+    };
+
+    Ok(snapshot.unwrap_or_default())
+}
+
+/// Changed properties and assets with their old and new values
+fn changes(before: &Value, after: &Value) -> Map<String, Value> {
+    let empty = Map::new();
+    let mut changes = Map::new();
+
+    for member in ["properties", "assets"] {
+        let old = before[member].as_object().unwrap_or(&empty);
+        let new = after[member].as_object().unwrap_or(&empty);
+
+        let changed: Map<String, Value> = old
+            .keys()
+            .chain(new.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|key| old.get(*key) != new.get(*key))
+            .map(|key| {
+                (
+                    key.to_owned(),
+                    json!({ "old": old.get(key), "new": new.get(key) }),
+                )
+            })
+            .collect();
+
+        if !changed.is_empty() {
+            changes.insert(member.to_string(), Value::Object(changed));
+        }
+    }
+
+    changes
+}
+
+/// Request id set by the client or proxy if it is reasonably short, a new one otherwise
+fn request_id(header: Option<&HeaderValue>) -> String {
+    header
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// Record mutating requests in the audit log
+pub(crate) async fn record(req: Request<Body>, next: Next<Body>, pool: PgPool) -> Response {
+    let method = req.method().clone();
+    if matches!(method, Method::GET | Method::HEAD | Method::OPTIONS)
+        || req.uri().path().trim_end_matches('/') == "/search"
+    {
+        return next.run(req).await;
+    }
+
+    let principal = req
+        .extensions()
+        .get::<User>()
+        .map(|user| user.name.to_owned());
+    let path = req.uri().path().to_owned();
+    let request_id = request_id(req.headers().get(REQUEST_ID));
+
+    // Inspect the body to describe the change
+    let (mut parts, body) = req.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => return Error::Exception(StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let (collection, item, mut summary) = describe(
+        &method,
+        &segments,
+        &serde_json::from_slice(&bytes).unwrap_or_default(),
+    );
+
+    // Record the changed fields from the state before and after the request
+    let target = collection.as_deref().map(|c| (c, item.as_deref()));
+    let before = match target {
+        Some((collection, item)) => snapshot(collection, item, &pool).await,
+        None => Ok(Value::Null),
+    };
+
+    let mut response = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+
+    if let (Some((collection, item)), Ok(before)) = (target, before) {
+        match snapshot(collection, item, &pool).await {
+            Ok(after) => {
+                let changes = changes(&before, &after);
+                if !changes.is_empty() {
+                    summary["changes"] = Value::Object(changes);
+                }
+            }
+            Err(e) => tracing::warn!("failed to read changes of request `{request_id}`: {e}"),
+        }
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+
+    let result = sqlx::query(
+        r#"
+        INSERT INTO meta.audit_log (principal, method, path, collection, item, status, request_id, summary)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+    )
+    .bind(principal)
+    .bind(method.as_str())
+    .bind(&path)
+    .bind(collection)
+    .bind(item)
+    .bind(response.status().as_u16() as i32)
+    .bind(&request_id)
+    .bind(Jsonb(summary))
+    .execute(&pool)
+    .await;
+
+    if let Err(e) = result {
+        tracing::warn!("failed to record request `{request_id}` in audit log: {e}");
+    }
+
+    response
+}
+
+/// Audit log query parameters
+#[derive(Deserialize)]
+pub(crate) struct AuditQuery {
+    /// Entries at or after
+    from: Option<DateTime<Utc>>,
+    /// Entries before
+    to: Option<DateTime<Utc>>,
+    /// Authenticated principal
+    user: Option<String>,
+    /// Collection `id`
+    collection: Option<String>,
+    /// Item `id`
+    item: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Query the audit log, latest first
+pub(crate) async fn entries(
+    Query(query): Query<AuditQuery>,
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Vec<Entry>>> {
+    let entries = sqlx::query_as(
+        r#"
+        SELECT * FROM meta.audit_log
+        WHERE ($1::timestamptz IS NULL OR time >= $1)
+            AND ($2::timestamptz IS NULL OR time < $2)
+            AND ($3::text IS NULL OR principal = $3)
+            AND ($4::text IS NULL OR collection = $4)
+            AND ($5::text IS NULL OR item = $5)
+        ORDER BY time DESC
+        LIMIT $6
+        "#,
+    )
+    .bind(query.from)
+    .bind(query.to)
+    .bind(query.user)
+    .bind(query.collection)
+    .bind(query.item)
+    .bind(query.limit.unwrap_or(LIMIT).clamp(1, MAX_LIMIT))
+    .fetch_all(&pool)
+    .await
+    .map_err(anyhow::Error::from)?;
+
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn process_inputs() {
+        let execution = |process: &str, inputs: Value| {
+            describe(
+                &Method::POST,
+                &["processes", process, "execution"],
+                &json!({ "inputs": inputs }),
+            )
+        };
+
+        // Item `id`, item object and item object wrapped as value
+        for item in [
+            json!("b"),
+            json!({"value": "b"}),
+            json!({"id": "b", "properties": {}}),
+            json!({"value": {"id": "b", "properties": {}}}),
+        ] {
+            let (collection, item, summary) = execution(
+                "load-asset",
+                json!({"collection": "a", "item": item, "id": "data", "key": "a/b/data.tif"}),
+            );
+            assert_eq!(collection.as_deref(), Some("a"));
+            assert_eq!(item.as_deref(), Some("b"));
+            assert_eq!(summary["asset"], "data");
+            assert_eq!(summary["key"], "a/b/data.tif");
+        }
+
+        let (collection, item, summary) = execution(
+            "move-asset",
+            json!({
+                "collection": "a",
+                "item": "b",
+                "id": "data",
+                "target": {"collection": "c", "item": "d", "id": "moved"}
+            }),
+        );
+        assert_eq!(
+            (collection.as_deref(), item.as_deref()),
+            (Some("a"), Some("b"))
+        );
+        assert_eq!(
+            summary["target"],
+            json!({"collection": "c", "item": "d", "asset": "moved", "key": null})
+        );
+
+        let (collection, item, summary) = execution(
+            "load-assets",
+            json!({"files": [
+                {"collection": "a", "item": {"value": "b"}, "key": "a/1.tif"},
+                {"collection": "c", "item": {"value": {"id": "d"}}, "key": "c/2.tif"}
+            ]}),
+        );
+        assert_eq!((collection, item), (None, None));
+        assert_eq!(
+            summary["files"],
+            json!([
+                {"collection": "a", "item": "b", "asset": null, "key": "a/1.tif"},
+                {"collection": "c", "item": "d", "asset": null, "key": "c/2.tif"}
+            ])
+        );
+    }
+
+    #[test]
+    fn changed_fields() {
+        let before = json!({
+            "properties": {"datetime": "2022-07-04T00:00:00Z", "title": "Radar"},
+            "assets": {"data": {"href": "a.tif"}, "old": {"href": "b.tif"}}
+        });
+        let after = json!({
+            "properties": {"datetime": "2022-07-05T00:00:00Z", "title": "Radar"},
+            "assets": {"data": {"href": "a.tif"}, "new": {"href": "c.tif"}}
+        });
+
+        assert_eq!(
+            Value::Object(changes(&before, &after)),
+            json!({
+                "properties": {
+                    "datetime": {"old": "2022-07-04T00:00:00Z", "new": "2022-07-05T00:00:00Z"}
+                },
+                "assets": {
+                    "new": {"old": null, "new": {"href": "c.tif"}},
+                    "old": {"old": {"href": "b.tif"}, "new": null}
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn created_and_unchanged() {
+        let item = json!({"properties": {"title": "Radar"}, "assets": null});
+
+        assert_eq!(
+            Value::Object(changes(&Value::Null, &item)),
+            json!({"properties": {"title": {"old": null, "new": "Radar"}}})
+        );
+        assert!(changes(&item, &item).is_empty());
+    }
+
+    #[test]
+    fn bounded_request_id() {
+        let id = HeaderValue::from_static("c0ffee");
+        assert_eq!(request_id(Some(&id)), "c0ffee");
+
+        let long = HeaderValue::from_str(&"x".repeat(MAX_REQUEST_ID_LEN + 1)).unwrap();
+        let generated = request_id(Some(&long));
+        assert!(Uuid::parse_str(&generated).is_ok());
+
+        assert!(Uuid::parse_str(&request_id(None)).is_ok());
+    }
+}
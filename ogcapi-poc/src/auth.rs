@@ -1,72 +1,281 @@
-use std::env::VarError;
+use std::{env::VarError, sync::Arc};
 
 use axum::{
     body::BoxBody,
-    headers::{
-        authorization::{Basic, Credentials},
-        Authorization,
-    },
-    http::{header::AUTHORIZATION, Method, Request, Response, StatusCode},
+    extract::MatchedPath,
+    headers::authorization::{Basic, Bearer, Credentials},
+    http::{header::AUTHORIZATION, HeaderMap, Request, Response, StatusCode},
     response::IntoResponse,
 };
+use futures::future::BoxFuture;
 use once_cell::sync::OnceCell;
-use tower_http::auth::AuthorizeRequest;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use subtle::ConstantTimeEq;
+use tower_http::auth::AsyncAuthorizeRequest;
 
 use ogcapi_services::Error;
 
-static BASIC: OnceCell<Basic> = OnceCell::new();
+use crate::{
+    jwt,
+    keys::{self, Scope},
+    limits, lockout,
+    policy::{Access, Policy},
+    users,
+};
+
+/// SHA-256 digests of `APP_USER` and `APP_PASSWORD`
+static BASIC: OnceCell<(Vec<u8>, Vec<u8>)> = OnceCell::new();
+
+#[derive(Clone)]
+pub(crate) struct Auth {
+    pool: PgPool,
+    policy: Arc<Policy>,
+}
 
-#[derive(Clone, Copy)]
-pub(crate) struct Auth;
+impl Auth {
+    pub(crate) fn new(pool: PgPool, policy: Policy) -> Self {
+        Auth {
+            pool,
+            policy: Arc::new(policy),
+        }
+    }
+}
+
+/// Authenticated user, API key or token subject, added to the request extensions
+#[derive(Clone, Debug)]
+pub(crate) struct User {
+    pub(crate) name: String,
+    /// Grantees of collection write grants, `None` if unrestricted
+    pub(crate) grantees: Option<Vec<String>>,
+    /// Whether all private collections are readable
+    pub(crate) read_private: bool,
+}
+
+impl User {
+    /// User with scopes, restricted to the grants of its grantees unless it is an admin
+    fn new(name: String, scopes: &[Scope], grantees: Vec<String>) -> Self {
+        let admin = scopes.contains(&Scope::Admin);
+        User {
+            name,
+            grantees: (!admin).then(|| grantees),
+            read_private: admin || scopes.contains(&Scope::CollectionsRead),
+        }
+    }
+}
 
-impl<B> AuthorizeRequest<B> for Auth {
+impl<B> AsyncAuthorizeRequest<B> for Auth
+where
+    B: Send + 'static,
+{
+    type RequestBody = B;
     type ResponseBody = BoxBody;
+    type Future = BoxFuture<'static, Result<Request<B>, Response<Self::ResponseBody>>>;
 
-    fn authorize(&mut self, request: &mut Request<B>) -> Result<(), Response<Self::ResponseBody>> {
-        match *request.method() {
-            // Do not reqire authorization for GET and HEAD requests
-            Method::GET | Method::HEAD => Ok(()),
-            // Reqire basic authroization for everything else
-            _ => {
-                // Exempt STAC /search
-                if request.uri() == "/search" {
-                    return Ok(());
+    fn authorize(&mut self, mut request: Request<B>) -> Self::Future {
+        let pool = self.pool.clone();
+        let policy = self.policy.clone();
+
+        Box::pin(async move {
+            // Evaluate the policy against the matched route
+            let path = request.uri().path();
+            let route = request
+                .extensions()
+                .get::<MatchedPath>()
+                .map_or(path, MatchedPath::as_str);
+            let access = policy.access(request.method(), route, path);
+
+            let scope = match access {
+                // Public requests are authenticated if credentials are given, e.g. to
+                // read private collections
+                Access::Public if !request.headers().contains_key(AUTHORIZATION) => {
+                    return Ok(request)
                 }
-                if let Some(auth) = request.headers().get(AUTHORIZATION) {
-                    let basic = BASIC.get_or_try_init(|| -> Result<Basic, VarError> {
-                        Ok(Authorization::basic(
-                            &std::env::var("APP_USER")?,
-                            &std::env::var("APP_PASSWORD")?,
-                        )
-                        .0)
-                    });
-
-                    match basic {
-                        Ok(basic) => {
-                            if Some(basic) == Credentials::decode(auth).as_ref() {
-                                Ok(())
-                            } else {
-                                Err(Error::Exception(
-                                    StatusCode::UNAUTHORIZED,
-                                    "Invalid credentials".to_string(),
-                                )
-                                .into_response())
-                            }
-                        }
-                        Err(_) => Err(Error::Exception(
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            "Credentials must be set".to_string(),
-                        )
-                        .into_response()),
-                    }
-                } else {
-                    Err(Error::Exception(
-                        StatusCode::UNAUTHORIZED,
-                        "Basic authorization required".to_string(),
+                Access::Public | Access::Authenticated => None,
+                Access::Scope(scope) => Some(scope),
+            };
+
+            // Brute-force protection
+            let ip = limits::client_ip(&request);
+            lockout::check(request.headers(), &ip)?;
+
+            let user = authenticate(request.headers(), &ip, scope, &pool)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            request.extensions_mut().insert(user);
+
+            Ok(request)
+        })
+    }
+}
+
+/// Authenticate with a JWT or API key (bearer token) or basic credentials
+async fn authenticate(
+    headers: &HeaderMap,
+    ip: &str,
+    scope: Option<Scope>,
+    pool: &PgPool,
+) -> Result<User, Error> {
+    let auth = headers.get(AUTHORIZATION).ok_or_else(|| {
+        Error::Exception(
+            StatusCode::UNAUTHORIZED,
+            "Basic or bearer authorization required".to_string(),
+        )
+    })?;
+
+    if let Some(bearer) = Bearer::decode(auth) {
+        // JWT of the identity provider
+        if jwt::enabled() && !keys::is_key(bearer.token()) {
+            let identity = jwt::verify(bearer.token()).await.map_err(|e| {
+                tracing::debug!("invalid token: {e}");
+                Error::Exception(StatusCode::UNAUTHORIZED, format!("Invalid token: {e}"))
+            })?;
+
+            require(scope, &identity.scopes, "Token")?;
+
+            let grantees = identity
+                .roles
+                .iter()
+                .map(|role| format!("role:{role}"))
+                .chain([format!("subject:{}", identity.subject)])
+                .collect();
+
+            return Ok(User::new(identity.name, &identity.scopes, grantees));
+        }
+
+        let key = keys::verify(bearer.token(), pool).await?.ok_or_else(|| {
+            Error::Exception(StatusCode::UNAUTHORIZED, "Invalid API key".to_string())
+        })?;
+
+        let scopes = key.scopes();
+        require(scope, &scopes, "API key")?;
+
+        return Ok(User::new(
+            key.name,
+            &scopes,
+            vec![format!("key:{}", key.id)],
+        ));
+    }
+
+    let credentials = Basic::decode(auth).ok_or_else(|| {
+        Error::Exception(
+            StatusCode::UNAUTHORIZED,
+            "Basic or bearer authorization required".to_string(),
+        )
+    })?;
+
+    let scopes = match users::path() {
+        // Users file with hashed passwords
+        Some(path) => users::verify(
+            path,
+            credentials.username().to_string(),
+            credentials.password().to_string(),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to verify credentials: {e}");
+            Error::Exception(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to verify credentials".to_string(),
+            )
+        })?,
+        // Single user from `APP_USER` and `APP_PASSWORD` with all scopes
+        None => {
+            let (user, password) = BASIC
+                .get_or_try_init(|| -> Result<_, VarError> {
+                    Ok((
+                        digest(&std::env::var("APP_USER")?),
+                        digest(&std::env::var("APP_PASSWORD")?),
+                    ))
+                })
+                .map_err(|_| {
+                    Error::Exception(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Credentials must be set".to_string(),
                     )
-                    .into_response())
-                }
-            }
+                })?;
+
+            // Constant-time comparison of fixed length digests
+            let user = digest(credentials.username()).ct_eq(user.as_slice());
+            let password = digest(credentials.password()).ct_eq(password.as_slice());
+            bool::from(user & password).then(|| Scope::ALL.to_vec())
+        }
+    };
+
+    let scopes = match scopes {
+        Some(scopes) => scopes,
+        None => {
+            lockout::failed(credentials.username(), ip);
+            return Err(Error::Exception(
+                StatusCode::UNAUTHORIZED,
+                "Invalid credentials".to_string(),
+            ));
         }
+    };
+    lockout::succeeded(credentials.username(), ip);
+
+    require(scope, &scopes, "User")?;
+
+    Ok(User::new(
+        credentials.username().to_string(),
+        &scopes,
+        vec![format!("user:{}", credentials.username())],
+    ))
+}
+
+/// Reject credentials lacking the scope required by the policy
+fn require(scope: Option<Scope>, scopes: &[Scope], credentials: &str) -> Result<(), Error> {
+    match scope.filter(|scope| !scopes.contains(scope)) {
+        Some(scope) => Err(Error::Exception(
+            StatusCode::FORBIDDEN,
+            format!("{credentials} lacks scope `{scope}`"),
+        )),
+        None => Ok(()),
+    }
+}
+
+fn digest(value: &str) -> Vec<u8> {
+    Sha256::digest(value.as_bytes()).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_required() {
+        let scopes = [Scope::AssetsWrite];
+
+        assert!(require(None, &scopes, "User").is_ok());
+        assert!(require(Some(Scope::AssetsWrite), &scopes, "User").is_ok());
+        assert!(require(Some(Scope::CollectionsWrite), &scopes, "User").is_err());
+        // Admins are not implicitly granted other scopes
+        assert!(require(Some(Scope::AssetsWrite), &[Scope::Admin], "User").is_err());
+        // Users without scopes may only authenticate
+        assert!(require(Some(Scope::ProcessesExecute), &[], "User").is_err());
+    }
+
+    #[test]
+    fn user_grants() {
+        let grantees = vec!["user:loader".to_string()];
+
+        let user = User::new(
+            "loader".to_string(),
+            &[Scope::AssetsWrite],
+            grantees.clone(),
+        );
+        assert_eq!(user.grantees, Some(grantees.clone()));
+        assert!(!user.read_private);
+
+        let user = User::new(
+            "reader".to_string(),
+            &[Scope::CollectionsRead],
+            grantees.clone(),
+        );
+        assert!(user.read_private);
+
+        let user = User::new("admin".to_string(), &Scope::ALL, grantees);
+        assert_eq!(user.grantees, None);
+        assert!(user.read_private);
     }
 }
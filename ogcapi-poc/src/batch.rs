@@ -0,0 +1,141 @@
+use axum::{
+    async_trait,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{stream, StreamExt};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::processes::{Execute, Process};
+
+use crate::loader::{add_asset, create_asset, AssetLoaderInputs};
+
+/// Default and maximum number of files processed concurrently
+const CONCURRENCY: usize = 4;
+const MAX_CONCURRENCY: usize = 16;
+
+/// STAC Asset batch loader
+pub(crate) struct AssetBatchLoader;
+
+/// Asset batch loader input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct AssetBatchLoaderInputs {
+    /// Files to load, each with the same inputs as the `load-asset` process
+    files: Vec<AssetLoaderInputs>,
+    /// Number of files processed concurrently (at most 16)
+    concurrency: Option<usize>,
+}
+
+/// Asset batch loader output schema
+#[derive(Serialize, JsonSchema)]
+struct AssetBatchLoaderOutputs {
+    /// Result per file in the order of the input
+    results: Vec<FileResult>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct FileResult {
+    /// S3 key of the file
+    key: String,
+    /// Processing status
+    status: FileStatus,
+    /// URI of the created/updated Item or Collection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    /// Error message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum FileStatus {
+    Successful,
+    Failed,
+}
+
+#[async_trait]
+impl Processor for AssetBatchLoader {
+    fn id(&self) -> String {
+        "load-assets".to_string()
+    }
+    fn process(&self) -> Process {
+        // Config schema generation
+        let settings = SchemaSettings::default().with(|s| {
+            s.option_nullable = false;
+            s.option_add_null_type = false;
+            s.inline_subschemas = true;
+        });
+        let gen = settings.into_generator();
+
+        Process::new(
+            self.id(),
+            "0.1.0",
+            &serde_json::to_value(
+                &gen.clone()
+                    .into_root_schema_for::<AssetBatchLoaderInputs>()
+                    .schema,
+            )
+            .unwrap(),
+            &serde_json::to_value(&gen.into_root_schema_for::<AssetBatchLoaderOutputs>().schema)
+                .unwrap(),
+        )
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: AssetBatchLoaderInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let concurrency = inputs
+            .concurrency
+            .unwrap_or(CONCURRENCY)
+            .clamp(1, MAX_CONCURRENCY);
+
+        // Upload files concurrently
+        let uploads: Vec<_> = stream::iter(inputs.files)
+            .map(|inputs| async move {
+                let upload = create_asset(&inputs, state).await;
+                (inputs, upload)
+            })
+            .buffered(concurrency)
+            .collect()
+            .await;
+
+        // Update items/collections sequentially, assets of the same item would
+        // otherwise overwrite each other
+        let mut results = Vec::new();
+        for (inputs, upload) in uploads {
+            let key = inputs.key.to_owned();
+
+            let result = match upload {
+                Ok(upload) => add_asset(inputs, upload, state, url).await,
+                Err(e) => Err(e),
+            };
+
+            results.push(match result {
+                Ok(location) => FileResult {
+                    key,
+                    status: FileStatus::Successful,
+                    location: Some(location.url.to_string()),
+                    message: None,
+                },
+                Err(e) => {
+                    tracing::warn!("failed to load asset `{key}`: {e}");
+                    FileResult {
+                        key,
+                        status: FileStatus::Failed,
+                        location: None,
+                        message: Some(e.to_string()),
+                    }
+                }
+            });
+        }
+
+        Ok(Json(AssetBatchLoaderOutputs { results }).into_response())
+    }
+}
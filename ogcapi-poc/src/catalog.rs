@@ -0,0 +1,310 @@
+use axum::{
+    body::Body,
+    http::{
+        header::{CONTENT_LENGTH, ETAG, IF_MATCH},
+        HeaderValue, Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::{Map, Value};
+use sqlx::{types::Json, PgPool};
+
+use ogcapi_services::Error;
+
+/// Outcome of an atomic item update
+pub(crate) enum Update {
+    Updated,
+    /// The item does not exist
+    NotFound,
+    /// The item changed since the `If-Match` ETag was issued
+    Modified,
+}
+
+/// Quoted items table of a collection, collection ids are used as table names
+pub(crate) fn items_table(collection: &str) -> String {
+    format!(r#"items."{}""#, collection.replace('"', r#""""#))
+}
+
+/// Current ETag of an item, derived from its stored row
+pub(crate) async fn item_etag(
+    collection: &str,
+    id: &str,
+    pool: &PgPool,
+) -> anyhow::Result<Option<String>> {
+    let etag: Option<String> = sqlx::query_scalar(&format!(
+        "SELECT md5(t::text) FROM {} t WHERE t.id = $1",
+        items_table(collection)
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(etag.map(|etag| format!("\"{etag}\"")))
+}
+
+/// Asset of an item, or of the collection if no item is given
+pub(crate) async fn asset(
+    collection: &str,
+    item: Option<&str>,
+    id: &str,
+    pool: &PgPool,
+) -> anyhow::Result<Option<Value>> {
+    let asset: Option<Option<Json<Value>>> = match item {
+        Some(item) => {
+            sqlx::query_scalar(&format!(
+                "SELECT assets -> $2 FROM {} WHERE id = $1",
+                items_table(collection)
+            ))
+            .bind(item)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+        }
+        None => {
+            sqlx::query_scalar(
+                "SELECT collection -> 'assets' -> $2 FROM meta.collections WHERE id = $1",
+            )
+            .bind(collection)
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+        }
+    };
+
+    Ok(asset.flatten().map(|asset| asset.0))
+}
+
+/// Item properties and assets
+#[derive(sqlx::FromRow)]
+pub(crate) struct ItemRow {
+    pub(crate) id: String,
+    pub(crate) properties: Option<Json<Map<String, Value>>>,
+    pub(crate) assets: Option<Json<Map<String, Value>>>,
+}
+
+/// Items of a collection whose properties contain `properties`
+pub(crate) async fn items(
+    collection: &str,
+    properties: Map<String, Value>,
+    pool: &PgPool,
+) -> anyhow::Result<Vec<ItemRow>> {
+    let items = sqlx::query_as(&format!(
+        "SELECT id, properties, assets FROM {} WHERE properties @> $1",
+        items_table(collection)
+    ))
+    .bind(Json(properties))
+    .fetch_all(pool)
+    .await?;
+
+    Ok(items)
+}
+
+/// Merge assets and properties into an item in a single statement, so that
+/// concurrent updates of the same item do not drop each other's assets
+pub(crate) async fn merge_item(
+    collection: &str,
+    id: &str,
+    assets: Map<String, Value>,
+    properties: Option<Map<String, Value>>,
+    if_match: Option<&str>,
+    pool: &PgPool,
+) -> anyhow::Result<Update> {
+    let feature = Map::from_iter([
+        ("assets".to_string(), Value::Object(assets)),
+        (
+            "properties".to_string(),
+            Value::Object(properties.unwrap_or_default()),
+        ),
+    ]);
+
+    merge_feature(collection, id, &feature, if_match, pool).await
+}
+
+/// Merge the members of a (partial) feature into an item in a single statement,
+/// `assets` and `properties` are merged, `geometry`, `bbox` and `links` replaced
+pub(crate) async fn merge_feature(
+    collection: &str,
+    id: &str,
+    feature: &Map<String, Value>,
+    if_match: Option<&str>,
+    pool: &PgPool,
+) -> anyhow::Result<Update> {
+    let result = sqlx::query(&format!(
+        r#"
+        UPDATE {table} t
+        SET assets = COALESCE(t.assets, '{{}}'::jsonb) || COALESCE($2 -> 'assets', '{{}}'::jsonb),
+            properties = COALESCE(t.properties, '{{}}'::jsonb)
+                || COALESCE($2 -> 'properties', '{{}}'::jsonb),
+            geom = COALESCE(ST_GeomFromGeoJSON(NULLIF($2 -> 'geometry', 'null'::jsonb)::text), t.geom),
+            bbox = COALESCE($2 -> 'bbox', t.bbox),
+            links = COALESCE($2 -> 'links', t.links)
+        WHERE t.id = $1 AND ($3::text IS NULL OR md5(t::text) = trim(both '"' from $3))
+        "#,
+        table = items_table(collection)
+    ))
+    .bind(id)
+    .bind(Json(feature))
+    .bind(if_match)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() > 0 {
+        Ok(Update::Updated)
+    } else if item_etag(collection, id, pool).await?.is_some() {
+        Ok(Update::Modified)
+    } else {
+        Ok(Update::NotFound)
+    }
+}
+
+/// Remove an asset from an item in a single statement, returns the removed asset
+pub(crate) async fn remove_item_asset(
+    collection: &str,
+    id: &str,
+    asset: &str,
+    pool: &PgPool,
+) -> anyhow::Result<Option<Value>> {
+    let removed: Option<Option<Json<Value>>> = sqlx::query_scalar(&format!(
+        r#"
+        UPDATE {table} t
+        SET assets = t.assets - $2
+        FROM {table} old
+        WHERE t.id = $1 AND old.id = t.id
+        RETURNING old.assets -> $2
+        "#,
+        table = items_table(collection)
+    ))
+    .bind(id)
+    .bind(asset)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(removed.flatten().map(|asset| asset.0))
+}
+
+/// Merge assets into a collection in a single statement
+pub(crate) async fn merge_collection(
+    id: &str,
+    assets: Map<String, Value>,
+    pool: &PgPool,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE meta.collections
+        SET collection = jsonb_set(
+            collection, '{assets}', COALESCE(collection -> 'assets', '{}'::jsonb) || $2
+        )
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(Json(assets))
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remove an asset from a collection in a single statement, returns the removed asset
+pub(crate) async fn remove_collection_asset(
+    id: &str,
+    asset: &str,
+    pool: &PgPool,
+) -> anyhow::Result<Option<Value>> {
+    let removed: Option<Option<Json<Value>>> = sqlx::query_scalar(
+        r#"
+        UPDATE meta.collections c
+        SET collection = jsonb_set(c.collection, '{assets}', (old.collection -> 'assets') - $2)
+        FROM meta.collections old
+        WHERE c.id = $1 AND old.id = c.id AND old.collection -> 'assets' ? $2
+        RETURNING old.collection -> 'assets' -> $2
+        "#,
+    )
+    .bind(id)
+    .bind(asset)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(removed.flatten().map(|asset| asset.0))
+}
+
+/// Middleware adding `ETag` headers to items and passing the `If-Match` header
+/// of `load-asset` executions on to the process as `ifMatch` input. The item row
+/// is share locked while the response is built, so body and ETag match.
+pub(crate) async fn etag(req: Request<Body>, next: Next<Body>, pool: PgPool) -> Response {
+    let path = req.uri().path().trim_start_matches("/root").to_owned();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = req.method().clone();
+
+    match (&method, segments.as_slice()) {
+        (&Method::GET | &Method::HEAD, ["collections", collection, "items", id]) => {
+            let mut tx = match pool.begin().await {
+                Ok(tx) => tx,
+                Err(e) => {
+                    tracing::warn!("failed to compute etag: {e}");
+                    return next.run(req).await;
+                }
+            };
+
+            let etag: Result<Option<String>, sqlx::Error> = sqlx::query_scalar(&format!(
+                "SELECT md5(t::text) FROM {} t WHERE t.id = $1 FOR SHARE",
+                items_table(collection)
+            ))
+            .bind(*id)
+            .fetch_optional(&mut tx)
+            .await;
+
+            let mut response = next.run(req).await;
+
+            if let Err(e) = tx.commit().await {
+                tracing::warn!("failed to release item lock: {e}");
+            }
+
+            if response.status().is_success() {
+                match etag {
+                    Ok(Some(etag)) => {
+                        if let Ok(value) = HeaderValue::from_str(&format!("\"{etag}\"")) {
+                            response.headers_mut().insert(ETAG, value);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("failed to compute etag: {e}"),
+                }
+            }
+
+            response
+        }
+        (&Method::POST, ["processes", "load-asset", "execution"])
+            if req.headers().contains_key(IF_MATCH) =>
+        {
+            let if_match = req.headers()[IF_MATCH]
+                .to_str()
+                .unwrap_or_default()
+                .to_owned();
+
+            let (mut parts, body) = req.into_parts();
+            parts.headers.remove(CONTENT_LENGTH);
+
+            let bytes = match hyper::body::to_bytes(body).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    return Error::Exception(StatusCode::BAD_REQUEST, e.to_string()).into_response()
+                }
+            };
+
+            let body = match serde_json::from_slice::<Value>(&bytes) {
+                Ok(mut execute) => {
+                    if let Some(inputs) = execute.get_mut("inputs").and_then(Value::as_object_mut) {
+                        inputs.insert("ifMatch".to_string(), Value::String(if_match));
+                    }
+                    Body::from(execute.to_string())
+                }
+                Err(_) => Body::from(bytes),
+            };
+
+            next.run(Request::from_parts(parts, body)).await
+        }
+        _ => next.run(req).await,
+    }
+}
@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use aws_sdk_s3::presigning::config::PresigningConfig;
+use aws_smithy_types_convert::date_time::DateTimeExt;
+use axum::{
+    async_trait,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use uuid::Uuid;
+
+use ogcapi_drivers::s3::{ByteStream, S3};
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::{
+    processes::{Execute, Process},
+    stac::Asset,
+};
+
+use crate::{
+    catalog,
+    cog::COG,
+    media_type::NETCDF,
+    raster::{self, Band, Raster, TargetCrs},
+    AWS_S3_BUCKET,
+};
+
+/// Key prefix of temporary clips
+const PREFIX: &str = "tmp/clips";
+
+/// Lifetime of temporary clips and their links
+const EXPIRY: Duration = Duration::from_secs(3600);
+
+/// Raster subsetting
+pub(crate) struct RasterClipper;
+
+/// Raster clip input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct RasterClipperInputs {
+    /// Collection `id`
+    collection: String,
+    /// Item `id`
+    item: String,
+    /// Asset `id` of the GeoTIFF, NetCDF or GRIB2 file
+    asset: String,
+    /// Bounding box `[min_x, min_y, max_x, max_y]`
+    bbox: [f64; 4],
+    /// CRS of the bounding box
+    #[serde(default)]
+    crs: TargetCrs,
+    /// Band, e.g. the time step of a NetCDF file, starting at 1
+    band: Option<isize>,
+    /// GRIB parameter (element), e.g. `T`
+    parameter: Option<String>,
+    /// GRIB level, e.g. `2000-HTGL`, requires `parameter`
+    level: Option<String>,
+    /// Output format
+    #[serde(default)]
+    format: Format,
+    /// Return the file directly (`value`) or a temporary link (`reference`)
+    #[serde(default)]
+    transmission: Transmission,
+}
+
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    GeoTiff,
+    NetCdf,
+}
+
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Transmission {
+    #[default]
+    Value,
+    Reference,
+}
+
+/// Raster clip output schema (`reference` transmission)
+#[derive(Serialize, JsonSchema)]
+struct RasterClipperOutputs {
+    /// Temporary link to the clipped file
+    href: String,
+    /// Expiration of the link
+    expires: String,
+}
+
+#[async_trait]
+impl Processor for RasterClipper {
+    fn id(&self) -> String {
+        "clip-raster".to_string()
+    }
+    fn process(&self) -> Process {
+        // Config schema generation
+        let settings = SchemaSettings::default().with(|s| {
+            s.option_nullable = false;
+            s.option_add_null_type = false;
+            s.inline_subschemas = true;
+        });
+        let gen = settings.into_generator();
+
+        Process::new(
+            self.id(),
+            "0.1.0",
+            &serde_json::to_value(
+                &gen.clone()
+                    .into_root_schema_for::<RasterClipperInputs>()
+                    .schema,
+            )
+            .unwrap(),
+            &serde_json::to_value(&gen.into_root_schema_for::<RasterClipperOutputs>().schema)
+                .unwrap(),
+        )
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: RasterClipperInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let source = catalog::asset(
+            &inputs.collection,
+            Some(&inputs.item),
+            &inputs.asset,
+            &state.db.pool,
+        )
+        .await?
+        .and_then(|asset| serde_json::from_value::<Asset>(asset).ok())
+        .ok_or_else(|| {
+            Error::Exception(
+                StatusCode::NOT_FOUND,
+                format!("Asset `{}` not found", inputs.asset),
+            )
+        })?;
+
+        let band = match (inputs.parameter, inputs.level) {
+            (Some(parameter), Some(level)) => Band::Level(parameter, level),
+            (Some(parameter), None) => Band::Element(parameter),
+            (None, Some(_)) => {
+                return Err(Error::Exception(
+                    StatusCode::BAD_REQUEST,
+                    "`level` requires `parameter`".to_string(),
+                ))
+            }
+            (None, None) => Band::Index(inputs.band.unwrap_or(1)),
+        };
+
+        // Clip
+        let bytes = raster::fetch(&source.href, &state.s3).await?;
+        let raster = match Raster::from_bytes(bytes, band).await {
+            Ok(raster) => {
+                raster
+                    .clip(inputs.bbox, inputs.crs.code().to_string())
+                    .await
+            }
+            Err(e) => Err(e),
+        }
+        .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let (bytes, media_type, extension) = match inputs.format {
+            Format::GeoTiff => (raster.into_cog().await?, COG, "tif"),
+            Format::NetCdf => (raster.into_netcdf().await?, NETCDF, "nc"),
+        };
+
+        match inputs.transmission {
+            Transmission::Value => Ok(([(CONTENT_TYPE, media_type)], bytes).into_response()),
+            Transmission::Reference => {
+                let stem = inputs.asset.split('.').next().unwrap_or_default();
+                let key = format!("{PREFIX}/{}/{stem}.{extension}", Uuid::new_v4());
+
+                state
+                    .s3
+                    .client
+                    .put_object()
+                    .bucket(AWS_S3_BUCKET)
+                    .key(&key)
+                    .body(ByteStream::from(bytes))
+                    .content_type(media_type)
+                    .send()
+                    .await
+                    .context("Failed to upload clip")?;
+
+                let presigned = state
+                    .s3
+                    .client
+                    .get_object()
+                    .bucket(AWS_S3_BUCKET)
+                    .key(&key)
+                    .presigned(PresigningConfig::expires_in(EXPIRY).context("Invalid expiry")?)
+                    .await
+                    .context("Failed to presign clip")?;
+
+                let expires = Utc::now() + chrono::Duration::from_std(EXPIRY).unwrap();
+
+                Ok(Json(RasterClipperOutputs {
+                    href: presigned.uri().to_string(),
+                    expires: expires.to_rfc3339(),
+                })
+                .into_response())
+            }
+        }
+    }
+}
+
+/// Remove expired temporary clips
+pub(crate) async fn cleanup() -> anyhow::Result<()> {
+    let s3 = S3::new().await;
+    let now = Utc::now();
+
+    let mut paginator = s3
+        .client
+        .list_objects_v2()
+        .bucket(AWS_S3_BUCKET)
+        .prefix(PREFIX)
+        .into_paginator()
+        .send();
+
+    while let Some(resp) = paginator.next().await {
+        for object in resp?.contents().unwrap_or_default() {
+            let expired = object.last_modified.map_or(false, |modified| {
+                (now - modified.to_chrono_utc())
+                    .to_std()
+                    .unwrap_or_default()
+                    > EXPIRY
+            });
+
+            if let (true, Some(key)) = (expired, object.key()) {
+                s3.delete_object(AWS_S3_BUCKET, key).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
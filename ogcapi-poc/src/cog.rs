@@ -0,0 +1,194 @@
+use anyhow::Context;
+use axum::{
+    async_trait,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use sqlx::PgPool;
+use url::Url;
+
+use ogcapi_drivers::s3::{ByteStream, S3};
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::{
+    processes::{Execute, Process},
+    stac::Asset,
+};
+
+use crate::{
+    assets::collection_key,
+    catalog::{self, Update},
+    loader,
+    raster::{self, Raster},
+    visibility, AWS_S3_BUCKET, AWS_S3_BUCKET_BASE,
+};
+
+/// Media type of Cloud Optimized GeoTIFFs
+pub(crate) static COG: &str = "image/tiff; application=geotiff; profile=cloud-optimized";
+
+/// Raster to Cloud Optimized GeoTIFF converter
+pub(crate) struct CogConverter;
+
+/// COG converter input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct CogConverterInputs {
+    /// Collection `id`
+    collection: String,
+    /// Item `id`
+    item: String,
+    /// Asset `id` of the raster (e.g. ODIM HDF5) to convert
+    asset: String,
+    /// Asset `id` of the COG, defaults to the source `id` with `.tif` extension
+    id: Option<String>,
+    /// S3 key of the COG, defaults to the source key with `.tif` extension if it is
+    /// within the collection prefix, to `{collection}/{item}/{asset}.tif` otherwise
+    key: Option<String>,
+    /// Raster band, starting at 1
+    band: Option<isize>,
+}
+
+/// COG converter output schema
+#[derive(Serialize, JsonSchema)]
+struct CogConverterOutputs {
+    /// Href of the COG
+    href: String,
+}
+
+#[async_trait]
+impl Processor for CogConverter {
+    fn id(&self) -> String {
+        "convert-cog".to_string()
+    }
+    fn process(&self) -> Process {
+        // Config schema generation
+        let settings = SchemaSettings::default().with(|s| {
+            s.option_nullable = false;
+            s.option_add_null_type = false;
+            s.inline_subschemas = true;
+        });
+        let gen = settings.into_generator();
+
+        Process::new(
+            self.id(),
+            "0.1.0",
+            &serde_json::to_value(
+                &gen.clone()
+                    .into_root_schema_for::<CogConverterInputs>()
+                    .schema,
+            )
+            .unwrap(),
+            &serde_json::to_value(&gen.into_root_schema_for::<CogConverterOutputs>().schema)
+                .unwrap(),
+        )
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: CogConverterInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let source = catalog::asset(
+            &inputs.collection,
+            Some(&inputs.item),
+            &inputs.asset,
+            &state.db.pool,
+        )
+        .await?
+        .and_then(|asset| serde_json::from_value::<Asset>(asset).ok())
+        .ok_or_else(|| {
+            Error::Exception(
+                StatusCode::NOT_FOUND,
+                format!("Asset `{}` not found", inputs.asset),
+            )
+        })?;
+
+        let key = match inputs.key {
+            Some(key) => key.trim_start_matches('/').to_owned(),
+            // Keep derived objects within the collection prefix
+            None => match collection_key(&source.href, &inputs.collection) {
+                Some(key) => with_tif(key),
+                None => format!(
+                    "{}/{}/{}",
+                    inputs.collection,
+                    inputs.item,
+                    with_tif(&inputs.asset)
+                ),
+            },
+        };
+        let id = inputs.id.unwrap_or_else(|| with_tif(&inputs.asset));
+
+        let asset = convert(
+            &inputs.collection,
+            &inputs.item,
+            &id,
+            &source,
+            &key,
+            inputs.band.unwrap_or(1),
+            &state.db.pool,
+            &state.s3,
+        )
+        .await?;
+
+        Ok(Json(CogConverterOutputs { href: asset.href }).into_response())
+    }
+}
+
+/// Replace the extension of a file name or key with `.tif`
+pub(crate) fn with_tif(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() && !stem.ends_with('/') => format!("{stem}.tif"),
+        _ => format!("{name}.tif"),
+    }
+}
+
+/// Convert the raster of the `source` asset to a COG and attach it to the item
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn convert(
+    collection: &str,
+    item: &str,
+    id: &str,
+    source: &Asset,
+    key: &str,
+    band: isize,
+    pool: &PgPool,
+    s3: &S3,
+) -> anyhow::Result<Asset> {
+    let bytes = raster::fetch(&source.href, s3).await?;
+    let raster = Raster::from_bytes(bytes, band).await?;
+
+    let cog = raster.into_cog().await?;
+
+    // Upload
+    let created = !loader::object_exists(key, s3).await;
+    s3.client
+        .put_object()
+        .bucket(AWS_S3_BUCKET)
+        .key(key)
+        .body(ByteStream::from(cog))
+        .content_type(COG)
+        .acl(visibility::acl(collection, pool).await?)
+        .send()
+        .await
+        .context("Failed to upload COG")?;
+
+    let mut asset = Asset::new(format!("{AWS_S3_BUCKET_BASE}/{key}"));
+    asset.title = source.title.to_owned();
+    asset.description = source.description.to_owned();
+    asset.r#type = Some(COG.to_string());
+    asset.roles = source.roles.to_owned();
+
+    // Attach to item, alongside the original
+    let assets = Map::from_iter([(id.to_owned(), serde_json::to_value(&asset).unwrap())]);
+    let e = match catalog::merge_item(collection, item, assets, None, None, pool).await {
+        Ok(Update::Updated) => return Ok(asset),
+        Ok(_) => anyhow::anyhow!("Item `{item}` not found"),
+        Err(e) => e,
+    };
+    if created {
+        anyhow::bail!("{e}, {}", loader::remove_upload(key, s3).await);
+    }
+    Err(e)
+}
@@ -0,0 +1,380 @@
+use anyhow::Context;
+use axum::{
+    async_trait,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use ogcapi_drivers::{postgres::Db, s3::ByteStream, s3::S3, FeatureTransactions};
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::{
+    common::Crs,
+    processes::{Execute, Process},
+    stac::Asset,
+};
+
+use crate::{
+    catalog::{self, Update},
+    cog::COG,
+    raster::{self, Band, Raster},
+    timeseries::is_raster,
+    visibility, AWS_S3_BUCKET, AWS_S3_BUCKET_BASE,
+};
+
+/// COSMO-1E collection
+pub(crate) const COSMO_1E: &str = "a6296aa9-d183-45c3-90fc-f03ec7d637be";
+
+/// Number of COSMO-1E ensemble members
+const MEMBERS: usize = 11;
+
+/// Ensemble statistics across members
+pub(crate) struct EnsembleStatistics;
+
+/// Ensemble statistics input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct EnsembleStatisticsInputs {
+    /// Collection `id`, defaults to COSMO-1E
+    collection: Option<String>,
+    /// `id` of any member Item of the ensemble
+    item: String,
+    /// Threshold for the exceedance probability
+    threshold: Option<f64>,
+    /// GRIB parameter (element), e.g. `T`, the first message if not set
+    parameter: Option<String>,
+}
+
+/// Ensemble statistics output schema
+#[derive(Serialize, JsonSchema)]
+struct EnsembleStatisticsOutputs {
+    /// `id` of the derived Item
+    item: String,
+    /// Number of members
+    members: usize,
+    /// Href of the derived assets
+    assets: Map<String, Value>,
+}
+
+#[async_trait]
+impl Processor for EnsembleStatistics {
+    fn id(&self) -> String {
+        "ensemble-statistics".to_string()
+    }
+    fn process(&self) -> Process {
+        // Config schema generation
+        let settings = SchemaSettings::default().with(|s| {
+            s.option_nullable = false;
+            s.option_add_null_type = false;
+            s.inline_subschemas = true;
+        });
+        let gen = settings.into_generator();
+
+        Process::new(
+            self.id(),
+            "0.1.0",
+            &serde_json::to_value(
+                &gen.clone()
+                    .into_root_schema_for::<EnsembleStatisticsInputs>()
+                    .schema,
+            )
+            .unwrap(),
+            &serde_json::to_value(
+                &gen.into_root_schema_for::<EnsembleStatisticsOutputs>()
+                    .schema,
+            )
+            .unwrap(),
+        )
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: EnsembleStatisticsInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let collection = inputs.collection.as_deref().unwrap_or(COSMO_1E);
+
+        let base = state
+            .drivers
+            .features
+            .read_feature(collection, &inputs.item, &Crs::default())
+            .await?
+            .ok_or_else(|| {
+                Error::Exception(
+                    StatusCode::NOT_FOUND,
+                    format!("Item `{}` not found", inputs.item),
+                )
+            })?;
+        let properties = base.properties.to_owned().unwrap_or_default();
+        if !properties.contains_key("member") {
+            return Err(Error::Exception(
+                StatusCode::BAD_REQUEST,
+                format!("Item `{}` is not an ensemble member", inputs.item),
+            ));
+        }
+
+        let outputs = compute(
+            collection,
+            &inputs.item,
+            inputs.threshold,
+            inputs.parameter,
+            &state.db,
+            &state.s3,
+        )
+        .await?;
+
+        Ok(Json(outputs).into_response())
+    }
+}
+
+/// Member items of the ensemble of `item`, i.e. with equal properties except `member`,
+/// returns the properties of `item` and the first raster asset of each member
+async fn find_members(
+    collection: &str,
+    item: &str,
+    db: &Db,
+) -> Result<(Map<String, Value>, Vec<(String, Asset)>)> {
+    let base = db
+        .read_feature(collection, item, &Crs::default())
+        .await?
+        .ok_or_else(|| {
+            Error::Exception(StatusCode::NOT_FOUND, format!("Item `{item}` not found"))
+        })?;
+
+    let mut properties = base.properties.to_owned().unwrap_or_default();
+    properties.remove("member");
+
+    let mut members = Vec::new();
+    for item in catalog::items(collection, properties.to_owned(), &db.pool).await? {
+        let member = item
+            .properties
+            .as_ref()
+            .and_then(|p| p.get("member"))
+            .and_then(Value::as_str);
+        if member.is_none() {
+            continue;
+        }
+
+        // First raster asset of the member
+        let asset = item
+            .assets
+            .map(|a| a.0)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(id, asset)| Some((id, serde_json::from_value::<Asset>(asset).ok()?)))
+            .find(|(id, asset)| is_raster(id, asset));
+
+        if let Some((_, asset)) = asset {
+            members.push((item.id, asset));
+        }
+    }
+    members.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok((base.properties.unwrap_or_default(), members))
+}
+
+/// `id` of the derived ensemble item, e.g. `..._leadtime_015_ensemble_parameter_T_...`
+fn ensemble_id(item: &str) -> String {
+    match item.find("member_") {
+        Some(start) => {
+            let end = item[start + 7..]
+                .find('_')
+                .map_or(item.len(), |i| start + 7 + i);
+            format!("{}ensemble{}", &item[..start], &item[end..])
+        }
+        None => format!("{item}_ensemble"),
+    }
+}
+
+/// Compute ensemble mean, spread, min/max and exceedance probability of the
+/// members of `item` and publish them as assets of a derived item
+async fn compute(
+    collection: &str,
+    item: &str,
+    threshold: Option<f64>,
+    parameter: Option<String>,
+    db: &Db,
+    s3: &S3,
+) -> Result<EnsembleStatisticsOutputs> {
+    let (properties, members) = find_members(collection, item, db).await?;
+    if members.len() < 2 {
+        return Err(Error::Exception(
+            StatusCode::BAD_REQUEST,
+            format!("Ensemble of `{item}` has less than two members"),
+        ));
+    }
+
+    // Read members
+    let mut rasters: Vec<Raster> = Vec::with_capacity(members.len());
+    for (id, asset) in &members {
+        let band = match &parameter {
+            Some(parameter) => Band::Element(parameter.to_owned()),
+            None => Band::Index(1),
+        };
+        let bytes = raster::fetch(&asset.href, s3).await?;
+        let raster = Raster::from_bytes(bytes, band)
+            .await
+            .with_context(|| format!("Failed to read member `{id}`"))?;
+
+        if let Some(first) = rasters.first() {
+            if (first.width, first.height) != (raster.width, raster.height) {
+                return Err(Error::Exception(
+                    StatusCode::BAD_REQUEST,
+                    format!("Grid of member `{id}` differs"),
+                ));
+            }
+        }
+        rasters.push(raster);
+    }
+
+    // Statistics per pixel
+    let first = &rasters[0];
+    let len = first.width * first.height;
+    let mut statistics: Vec<(&str, Vec<f64>)> = vec![
+        ("mean", vec![f64::NAN; len]),
+        ("spread", vec![f64::NAN; len]),
+        ("min", vec![f64::NAN; len]),
+        ("max", vec![f64::NAN; len]),
+    ];
+    if threshold.is_some() {
+        statistics.push(("probability", vec![f64::NAN; len]));
+    }
+
+    let mut values = Vec::with_capacity(rasters.len());
+    for i in 0..len {
+        values.clear();
+        values.extend(
+            rasters
+                .iter()
+                .filter_map(|r| Some(r.data[i]).filter(|v| r.is_valid(*v))),
+        );
+        if values.is_empty() {
+            continue;
+        }
+
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+
+        statistics[0].1[i] = mean;
+        statistics[1].1[i] = variance.sqrt();
+        statistics[2].1[i] = values.iter().cloned().fold(f64::INFINITY, f64::min);
+        statistics[3].1[i] = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        if let Some(threshold) = threshold {
+            statistics[4].1[i] = values.iter().filter(|v| **v > threshold).count() as f64 / n;
+        }
+    }
+
+    // Publish derived assets
+    let id = ensemble_id(item);
+    let mut assets = Map::new();
+    for (name, data) in statistics {
+        let raster = Raster {
+            width: first.width,
+            height: first.height,
+            data,
+            no_data: Some(f64::NAN),
+            geo_transform: first.geo_transform,
+            projection: first.projection.to_owned(),
+        };
+        let key = format!("{collection}/ensemble/{id}/{name}.tif");
+
+        s3.client
+            .put_object()
+            .bucket(AWS_S3_BUCKET)
+            .key(&key)
+            .body(ByteStream::from(raster.into_cog().await?))
+            .content_type(COG)
+            .acl(visibility::acl(collection, &db.pool).await?)
+            .send()
+            .await
+            .context("Failed to upload ensemble statistic")?;
+
+        let mut asset = Asset::new(format!("{AWS_S3_BUCKET_BASE}/{key}"));
+        asset.title = Some(match (name, threshold) {
+            ("probability", Some(threshold)) => format!("Probability of exceeding {threshold}"),
+            _ => format!("Ensemble {name}"),
+        });
+        asset.r#type = Some(COG.to_string());
+        asset.roles = vec!["data".to_string()];
+
+        assets.insert(name.to_string(), serde_json::to_value(asset).unwrap());
+    }
+
+    // Derived item next to the members
+    let mut properties = properties;
+    properties.remove("member");
+    properties.insert("ensemble-members".to_string(), json!(members.len()));
+    if let Some(threshold) = threshold {
+        properties.insert("ensemble-threshold".to_string(), json!(threshold));
+    }
+
+    match catalog::merge_item(
+        collection,
+        &id,
+        assets.to_owned(),
+        Some(properties.to_owned()),
+        None,
+        &db.pool,
+    )
+    .await?
+    {
+        Update::Updated => {}
+        _ => {
+            let base = db
+                .read_feature(collection, item, &Crs::default())
+                .await?
+                .ok_or_else(|| {
+                    Error::Exception(StatusCode::NOT_FOUND, format!("Item `{item}` not found"))
+                })?;
+
+            let feature = serde_json::from_value(json!({
+                "id": id,
+                "collection": collection,
+                "geometry": base.geometry,
+                "bbox": base.bbox,
+                "properties": properties,
+                "links": [{
+                    "href": format!("./{item}"),
+                    "rel": "derived_from",
+                    "type": "application/geo+json"
+                }],
+                "assets": assets
+            }))
+            .context("Failed to parse item")?;
+            db.create_feature(&feature).await?;
+        }
+    }
+
+    tracing::info!(
+        "computed ensemble statistics `{id}` of {} members",
+        members.len()
+    );
+
+    Ok(EnsembleStatisticsOutputs {
+        item: id,
+        members: members.len(),
+        assets: assets
+            .into_iter()
+            .map(|(name, asset)| (name, asset["href"].to_owned()))
+            .collect(),
+    })
+}
+
+/// Compute the ensemble statistics once all members of `item` arrived
+pub(crate) async fn complete(collection: &str, item: &str, db: &Db, s3: &S3) -> anyhow::Result<()> {
+    let (_, members) = find_members(collection, item, db)
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+
+    if members.len() == MEMBERS {
+        compute(collection, item, None, None, db, s3)
+            .await
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+    }
+
+    Ok(())
+}
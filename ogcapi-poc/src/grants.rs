@@ -0,0 +1,406 @@
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{header::CONTENT_LENGTH, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use ogcapi_services::{Error, Result};
+
+use crate::{auth::User, ensemble::COSMO_1E};
+
+/// Write access of a grantee to the collections matching a pattern
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub(crate) struct Grant {
+    id: String,
+    /// `key:<key id>`, `user:<user name>`, `role:<role>` or `subject:<token subject>`
+    grantee: String,
+    /// Collection `id`, `*` matches any sequence of characters
+    pattern: String,
+    created: DateTime<Utc>,
+}
+
+/// Create the collection grant table
+pub(crate) async fn setup(pool: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS meta.collection_grants (
+            id text PRIMARY KEY,
+            grantee text NOT NULL,
+            pattern text NOT NULL,
+            created timestamptz NOT NULL DEFAULT now(),
+            UNIQUE (grantee, pattern)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Whether a collection `id` matches a pattern with `*` wildcards
+pub(crate) fn matches(pattern: &str, id: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match id.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        // No wildcard
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
+/// Collection patterns granted to any of the grantees
+pub(crate) async fn patterns(grantees: &[String], pool: &PgPool) -> anyhow::Result<Vec<String>> {
+    let patterns =
+        sqlx::query_scalar("SELECT pattern FROM meta.collection_grants WHERE grantee = ANY($1)")
+            .bind(grantees)
+            .fetch_all(pool)
+            .await?;
+
+    Ok(patterns)
+}
+
+/// Whether any of the patterns grants access to the collection
+pub(crate) fn granted(patterns: &[String], collection: &str) -> bool {
+    patterns.iter().any(|pattern| matches(pattern, collection))
+}
+
+/// Input value, either plain or wrapped as `{"value": ...}`
+pub(crate) fn input<'a>(inputs: &'a Value, key: &str) -> Option<&'a Value> {
+    let value = inputs.get(key)?;
+    Some(value.get("value").unwrap_or(value))
+}
+
+/// Collection of the S3 `key` input, i.e. its first path segment
+fn key_collection(inputs: &Value) -> Option<String> {
+    input(inputs, "key")
+        .and_then(Value::as_str)
+        .and_then(|key| key.trim_start_matches('/').split('/').next())
+        .filter(|collection| !collection.is_empty())
+        .map(ToOwned::to_owned)
+}
+
+/// Collections involved in the execution of a process
+pub(crate) struct ProcessCollections {
+    /// Collections whose assets are read or written
+    pub(crate) all: Vec<String>,
+    /// Collections written to, including the collections of the S3 keys written to
+    pub(crate) written: Vec<String>,
+}
+
+/// Collections read and written by the execution of a process
+pub(crate) fn process_collections(process: &str, execute: &Value) -> ProcessCollections {
+    let inputs = execute.get("inputs").cloned().unwrap_or_default();
+    let collection = |inputs: &Value| {
+        input(inputs, "collection")
+            .and_then(Value::as_str)
+            .map(ToOwned::to_owned)
+    };
+    let with_key = |inputs: &Value| {
+        collection(inputs)
+            .into_iter()
+            .chain(key_collection(inputs))
+            .collect::<Vec<_>>()
+    };
+    let default = |collections: Vec<String>| match process {
+        "ensemble-statistics" if collections.is_empty() => vec![COSMO_1E.to_string()],
+        _ => collections,
+    };
+
+    let mut written = match process {
+        "load-asset" | "convert-cog" => with_key(&inputs),
+        "delete-asset" | "create-thumbnail" => collection(&inputs).into_iter().collect(),
+        "load-assets" => input(&inputs, "files")
+            .and_then(Value::as_array)
+            .map(|files| files.iter().flat_map(with_key).collect())
+            .unwrap_or_default(),
+        "move-asset" => with_key(&inputs)
+            .into_iter()
+            .chain(input(&inputs, "target").and_then(collection))
+            .collect(),
+        // Only stored as asset if an `id` is given
+        "convert-grib" if input(&inputs, "id").is_some() => {
+            collection(&inputs).into_iter().collect()
+        }
+        "ensemble-statistics" => default(collection(&inputs).into_iter().collect()),
+        _ => Vec::new(),
+    };
+    written.sort();
+    written.dedup();
+
+    let mut all = default(
+        collection(&inputs)
+            .into_iter()
+            .chain(input(&inputs, "target").and_then(collection))
+            .chain(["files", "items"].iter().flat_map(|key| {
+                input(&inputs, key)
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(collection)
+            }))
+            .chain(written.iter().cloned())
+            .collect(),
+    );
+    all.sort();
+    all.dedup();
+
+    ProcessCollections { all, written }
+}
+
+/// Enforce collection grants for transactional endpoints and processes
+/// writing to collections
+pub(crate) async fn enforce(req: Request<Body>, next: Next<Body>, pool: PgPool) -> Response {
+    // Unrestricted or public request
+    let grantees = match req.extensions().get::<User>() {
+        Some(User {
+            grantees: Some(grantees),
+            ..
+        }) => grantees.to_owned(),
+        _ => return next.run(req).await,
+    };
+
+    let path = req.uri().path().to_owned();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = req.method().clone();
+
+    let (req, collections) = match (&method, segments.as_slice()) {
+        (&Method::GET | &Method::HEAD, _) => return next.run(req).await,
+        (_, ["collections", collection, ..]) => (req, vec![collection.to_string()]),
+        (&Method::POST, ["collections"]) | (&Method::POST, ["processes", _, "execution"]) => {
+            let (mut parts, body) = req.into_parts();
+            parts.headers.remove(CONTENT_LENGTH);
+
+            let bytes = match hyper::body::to_bytes(body).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    return Error::Exception(StatusCode::BAD_REQUEST, e.to_string()).into_response()
+                }
+            };
+            let value: Value = serde_json::from_slice(&bytes).unwrap_or_default();
+
+            let collections = match segments.as_slice() {
+                ["processes", process, "execution"] => process_collections(process, &value).written,
+                _ => value
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map(ToOwned::to_owned)
+                    .into_iter()
+                    .collect(),
+            };
+
+            (Request::from_parts(parts, Body::from(bytes)), collections)
+        }
+        _ => (req, Vec::new()),
+    };
+
+    if collections.is_empty() {
+        return next.run(req).await;
+    }
+
+    let patterns = match patterns(&grantees, &pool).await {
+        Ok(patterns) => patterns,
+        Err(e) => {
+            return Error::Exception(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                .into_response()
+        }
+    };
+    if let Some(collection) = collections.iter().find(|c| !granted(&patterns, c)) {
+        return Error::Exception(
+            StatusCode::FORBIDDEN,
+            format!("Not permitted to write to collection `{collection}`"),
+        )
+        .into_response();
+    }
+
+    next.run(req).await
+}
+
+/// Grant creation request
+#[derive(Deserialize)]
+pub(crate) struct NewGrant {
+    grantee: String,
+    pattern: String,
+}
+
+/// List collection grants
+pub(crate) async fn list_grants(Extension(pool): Extension<PgPool>) -> Result<Json<Vec<Grant>>> {
+    let grants = sqlx::query_as(
+        "SELECT id, grantee, pattern, created FROM meta.collection_grants ORDER BY grantee, pattern",
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(anyhow::Error::from)?;
+
+    Ok(Json(grants))
+}
+
+/// Grant write access to collections
+pub(crate) async fn create_grant(
+    Extension(pool): Extension<PgPool>,
+    Json(new): Json<NewGrant>,
+) -> Result<Response> {
+    let valid_grantee = ["key:", "user:", "role:", "subject:"]
+        .iter()
+        .any(|prefix| new.grantee.len() > prefix.len() && new.grantee.starts_with(prefix));
+    if !valid_grantee || new.pattern.is_empty() {
+        return Err(Error::Exception(
+            StatusCode::BAD_REQUEST,
+            "Grantee must be `key:<id>`, `user:<name>`, `role:<role>` or `subject:<subject>` and pattern non-empty"
+                .to_string(),
+        ));
+    }
+
+    let grant: Grant = sqlx::query_as(
+        r#"
+        INSERT INTO meta.collection_grants (id, grantee, pattern)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (grantee, pattern) DO UPDATE SET pattern = EXCLUDED.pattern
+        RETURNING id, grantee, pattern, created
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&new.grantee)
+    .bind(&new.pattern)
+    .fetch_one(&pool)
+    .await
+    .map_err(anyhow::Error::from)?;
+
+    tracing::info!("granted `{}` access to `{}`", grant.grantee, grant.pattern);
+
+    Ok((StatusCode::CREATED, Json(grant)).into_response())
+}
+
+/// Revoke a collection grant
+pub(crate) async fn delete_grant(
+    Path(id): Path<String>,
+    Extension(pool): Extension<PgPool>,
+) -> Result<StatusCode> {
+    let result = sqlx::query("DELETE FROM meta.collection_grants WHERE id = $1")
+        .bind(&id)
+        .execute(&pool)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    if result.rows_affected() > 0 {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn pattern_matching() {
+        assert!(matches(
+            "ch.meteoschweiz.ogd-smn",
+            "ch.meteoschweiz.ogd-smn"
+        ));
+        assert!(!matches(
+            "ch.meteoschweiz.ogd-smn",
+            "ch.meteoschweiz.ogd-smn-precip"
+        ));
+        assert!(matches("ch.meteoschweiz.ogd-*", "ch.meteoschweiz.ogd-smn"));
+        assert!(matches("ch.meteoschweiz.ogd-*", "ch.meteoschweiz.ogd-"));
+        assert!(!matches("ch.meteoschweiz.ogd-*", "ch.meteoschweiz.ogd"));
+        assert!(matches("*-smn", "ch.meteoschweiz.ogd-smn"));
+        assert!(matches("ch.*.ogd-*", "ch.meteoschweiz.ogd-smn"));
+        assert!(!matches("ch.*.ogd-*", "ch.meteoschweiz.smn"));
+        assert!(matches("*", "any"));
+        assert!(!matches("a*a", "a"));
+    }
+
+    #[test]
+    fn collections_of_processes() {
+        let execute = |inputs: Value| json!({ "inputs": inputs });
+        let written =
+            |process: &str, inputs: Value| process_collections(process, &execute(inputs)).written;
+
+        assert_eq!(
+            written(
+                "load-asset",
+                json!({"collection": {"value": "a"}, "key": "b/item/file.tif"})
+            ),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            written(
+                "convert-cog",
+                json!({"collection": "a", "key": "/a/item/file.tif"})
+            ),
+            vec!["a"]
+        );
+        assert_eq!(
+            written(
+                "load-assets",
+                json!({"files": [
+                    {"collection": "a", "key": "a/1.tif"},
+                    {"collection": "b", "key": "c/2.tif"}
+                ]})
+            ),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            written(
+                "move-asset",
+                json!({
+                    "collection": "a",
+                    "target": {"collection": "b"},
+                    "key": "c/file.tif"
+                })
+            ),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            written("delete-asset", json!({"collection": "a"})),
+            vec!["a"]
+        );
+        assert!(written("convert-grib", json!({"collection": "a"})).is_empty());
+        assert_eq!(
+            written("convert-grib", json!({"collection": "a", "id": "x"})),
+            vec!["a"]
+        );
+        assert_eq!(written("ensemble-statistics", json!({})), vec![COSMO_1E]);
+        assert!(written("zonal-statistics", json!({"collection": "a"})).is_empty());
+
+        // Read collections are hidden from unauthorized callers as well
+        let items = execute(json!({"items": [{"collection": "a"}, {"collection": "b"}]}));
+        assert_eq!(
+            process_collections("zonal-statistics", &items).all,
+            vec!["a", "b"]
+        );
+        let key = execute(json!({"collection": "a", "key": "c/file.tif"}));
+        assert_eq!(process_collections("convert-cog", &key).all, vec!["a", "c"]);
+        assert_eq!(
+            process_collections("ensemble-statistics", &json!({})).all,
+            vec![COSMO_1E]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::Context;
+use axum::{
+    async_trait,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use url::Url;
+
+use ogcapi_drivers::s3::ByteStream;
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::{
+    processes::{Execute, Process},
+    stac::Asset,
+};
+
+use crate::{
+    assets::collection_key,
+    catalog::{self, Update},
+    cog::COG,
+    loader,
+    raster::{self, Band, Raster, TargetCrs},
+    visibility, AWS_S3_BUCKET, AWS_S3_BUCKET_BASE,
+};
+
+/// GRIB2 to GeoTIFF converter
+pub(crate) struct GribConverter;
+
+/// GRIB2 converter input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct GribConverterInputs {
+    /// Collection `id`
+    collection: String,
+    /// Item `id`
+    item: String,
+    /// Asset `id` of the GRIB2 file
+    asset: String,
+    /// GRIB parameter (element) to decode, e.g. `T`, the first message if not set
+    parameter: Option<String>,
+    /// Target CRS
+    #[serde(default)]
+    crs: TargetCrs,
+    /// Asset `id` of the GeoTIFF, the GeoTIFF is returned directly if not set
+    id: Option<String>,
+}
+
+/// GRIB2 converter output schema
+#[derive(Serialize, JsonSchema)]
+struct GribConverterOutputs {
+    /// Href of the GeoTIFF asset
+    href: String,
+}
+
+#[async_trait]
+impl Processor for GribConverter {
+    fn id(&self) -> String {
+        "convert-grib".to_string()
+    }
+    fn process(&self) -> Process {
+        // Config schema generation
+        let settings = SchemaSettings::default().with(|s| {
+            s.option_nullable = false;
+            s.option_add_null_type = false;
+            s.inline_subschemas = true;
+        });
+        let gen = settings.into_generator();
+
+        Process::new(
+            self.id(),
+            "0.1.0",
+            &serde_json::to_value(
+                &gen.clone()
+                    .into_root_schema_for::<GribConverterInputs>()
+                    .schema,
+            )
+            .unwrap(),
+            &serde_json::to_value(&gen.into_root_schema_for::<GribConverterOutputs>().schema)
+                .unwrap(),
+        )
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: GribConverterInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let source = catalog::asset(
+            &inputs.collection,
+            Some(&inputs.item),
+            &inputs.asset,
+            &state.db.pool,
+        )
+        .await?
+        .and_then(|asset| serde_json::from_value::<Asset>(asset).ok())
+        .ok_or_else(|| {
+            Error::Exception(
+                StatusCode::NOT_FOUND,
+                format!("Asset `{}` not found", inputs.asset),
+            )
+        })?;
+
+        // Decode and reproject
+        let bytes = raster::fetch(&source.href, &state.s3).await?;
+        let band = match &inputs.parameter {
+            Some(parameter) => Band::Element(parameter.to_owned()),
+            None => Band::Index(1),
+        };
+        let raster = Raster::from_bytes(bytes, band)
+            .await
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+        let tiff = raster
+            .reproject(inputs.crs.code().to_string())
+            .await?
+            .into_cog()
+            .await?;
+
+        // Direct download
+        let id = match inputs.id {
+            Some(id) => id,
+            None => return Ok(([(CONTENT_TYPE, COG)], tiff).into_response()),
+        };
+
+        // Store as asset alongside the original, within the collection prefix
+        let source_key = collection_key(&source.href, &inputs.collection)
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| format!("{}/{}/{}", inputs.collection, inputs.item, inputs.asset));
+        let stem = stem(&source_key);
+        let key = format!(
+            "{stem}_{}_{}.tif",
+            inputs.parameter.as_deref().unwrap_or("1"),
+            inputs.crs.code().replace(':', "")
+        );
+
+        let created = !loader::object_exists(&key, &state.s3).await;
+        state
+            .s3
+            .client
+            .put_object()
+            .bucket(AWS_S3_BUCKET)
+            .key(&key)
+            .body(ByteStream::from(tiff))
+            .content_type(COG)
+            .acl(visibility::acl(&inputs.collection, &state.db.pool).await?)
+            .send()
+            .await
+            .context("Failed to upload GeoTIFF")?;
+
+        let mut asset = Asset::new(format!("{AWS_S3_BUCKET_BASE}/{key}"));
+        asset.title = source.title;
+        asset.r#type = Some(COG.to_string());
+        asset.roles = vec!["data".to_string()];
+
+        let assets = Map::from_iter([(id, serde_json::to_value(&asset).unwrap())]);
+        let e = match catalog::merge_item(
+            &inputs.collection,
+            &inputs.item,
+            assets,
+            None,
+            None,
+            &state.db.pool,
+        )
+        .await
+        {
+            Ok(Update::Updated) => {
+                return Ok(Json(GribConverterOutputs { href: asset.href }).into_response())
+            }
+            Ok(_) => Error::NotFound,
+            Err(e) => e.into(),
+        };
+        if created {
+            return Err(loader::with_rollback(
+                e,
+                loader::remove_upload(&key, &state.s3).await,
+            ));
+        }
+        Err(e)
+    }
+}
+
+/// Name without the GRIB2 file extension (`.grib2` or `.grb2`)
+pub(crate) fn stem(name: &str) -> &str {
+    name.strip_suffix(".grib2")
+        .or_else(|| name.strip_suffix(".grb2"))
+        .unwrap_or(name)
+}
@@ -0,0 +1,288 @@
+use std::{fmt, str::FromStr};
+
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use ogcapi_services::{Error, Result};
+
+/// Prefix of issued API keys
+const TOKEN_PREFIX: &str = "poc_";
+
+/// Permission granted to an API key
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Scope {
+    /// Load, delete and move assets
+    #[serde(rename = "assets:write")]
+    AssetsWrite,
+    /// Read private collections
+    #[serde(rename = "collections:read")]
+    CollectionsRead,
+    /// Create, update and delete collections and items
+    #[serde(rename = "collections:write")]
+    CollectionsWrite,
+    /// Execute processes other than the asset processes
+    #[serde(rename = "processes:execute")]
+    ProcessesExecute,
+    /// Manage API keys
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    /// All scopes
+    pub(crate) const ALL: [Scope; 5] = [
+        Scope::AssetsWrite,
+        Scope::CollectionsRead,
+        Scope::CollectionsWrite,
+        Scope::ProcessesExecute,
+        Scope::Admin,
+    ];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Scope::AssetsWrite => "assets:write",
+            Scope::CollectionsRead => "collections:read",
+            Scope::CollectionsWrite => "collections:write",
+            Scope::ProcessesExecute => "processes:execute",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| anyhow::anyhow!("Unknown scope `{s}`"))
+    }
+}
+
+/// Stored API key, without its hash
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub(crate) struct ApiKey {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) scopes: Vec<String>,
+    pub(crate) created: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) revoked: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    /// Known scopes of the key
+    pub(crate) fn scopes(&self) -> Vec<Scope> {
+        self.scopes.iter().filter_map(|s| s.parse().ok()).collect()
+    }
+}
+
+/// Create the API key table
+pub(crate) async fn setup(pool: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS meta.api_keys (
+            id text PRIMARY KEY,
+            name text NOT NULL,
+            hash text NOT NULL UNIQUE,
+            scopes text[] NOT NULL,
+            created timestamptz NOT NULL DEFAULT now(),
+            revoked timestamptz
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Hex encoded SHA-256 hash of a token
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Issue a new API key, returns the key and the token, which is only shown once
+pub(crate) async fn issue(
+    name: &str,
+    scopes: &[Scope],
+    pool: &PgPool,
+) -> anyhow::Result<(ApiKey, String)> {
+    let token = format!(
+        "{TOKEN_PREFIX}{}{}",
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    );
+    let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+
+    let key = sqlx::query_as(
+        r#"
+        INSERT INTO meta.api_keys (id, name, hash, scopes)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, name, scopes, created, revoked
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(name)
+    .bind(hash(&token))
+    .bind(scopes)
+    .fetch_one(pool)
+    .await?;
+
+    Ok((key, token))
+}
+
+/// All API keys, latest first
+pub(crate) async fn list(pool: &PgPool) -> anyhow::Result<Vec<ApiKey>> {
+    let keys = sqlx::query_as(
+        "SELECT id, name, scopes, created, revoked FROM meta.api_keys ORDER BY created DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(keys)
+}
+
+/// Revoke an API key, returns `false` if no active key with `id` exists
+pub(crate) async fn revoke(id: &str, pool: &PgPool) -> anyhow::Result<bool> {
+    let result =
+        sqlx::query("UPDATE meta.api_keys SET revoked = now() WHERE id = $1 AND revoked IS NULL")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Whether a bearer token is an API key, as opposed to a JWT
+pub(crate) fn is_key(token: &str) -> bool {
+    token.starts_with(TOKEN_PREFIX)
+}
+
+/// Active API key of a token
+pub(crate) async fn verify(token: &str, pool: &PgPool) -> anyhow::Result<Option<ApiKey>> {
+    if !is_key(token) {
+        return Ok(None);
+    }
+
+    let key = sqlx::query_as(
+        r#"
+        SELECT id, name, scopes, created, revoked FROM meta.api_keys
+        WHERE hash = $1 AND revoked IS NULL
+        "#,
+    )
+    .bind(hash(token))
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(key)
+}
+
+/// API key creation request
+#[derive(Deserialize)]
+pub(crate) struct NewKey {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+/// Issued API key including the token
+#[derive(Serialize)]
+pub(crate) struct IssuedKey {
+    #[serde(flatten)]
+    key: ApiKey,
+    token: String,
+}
+
+/// List API keys
+pub(crate) async fn list_keys(Extension(pool): Extension<PgPool>) -> Result<Json<Vec<ApiKey>>> {
+    Ok(Json(list(&pool).await?))
+}
+
+/// Create an API key
+pub(crate) async fn create_key(
+    Extension(pool): Extension<PgPool>,
+    Json(new): Json<NewKey>,
+) -> Result<Response> {
+    if new.scopes.is_empty() {
+        return Err(Error::Exception(
+            StatusCode::BAD_REQUEST,
+            "At least one scope is required".to_string(),
+        ));
+    }
+
+    let (key, token) = issue(&new.name, &new.scopes, &pool).await?;
+
+    tracing::info!("issued API key `{}` ({})", key.name, key.id);
+
+    Ok((StatusCode::CREATED, Json(IssuedKey { key, token })).into_response())
+}
+
+/// Revoke an API key
+pub(crate) async fn revoke_key(
+    Path(id): Path<String>,
+    Extension(pool): Extension<PgPool>,
+) -> Result<StatusCode> {
+    if revoke(&id, &pool).await? {
+        tracing::info!("revoked API key `{id}`");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+/// Command line interface, e.g. `ogcapi-poc keys create <name> <scope>...`
+pub(crate) async fn cli(args: &[String]) -> anyhow::Result<()> {
+    let url = std::env::var("DATABASE_URL")?;
+    let pool = PgPool::connect(&url).await?;
+    setup(&pool).await?;
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["create", name, ref scopes @ ..] if !scopes.is_empty() => {
+            let scopes = scopes
+                .iter()
+                .map(|s| s.parse())
+                .collect::<anyhow::Result<Vec<Scope>>>()?;
+            let (key, token) = issue(name, &scopes, &pool).await?;
+            println!("{}\t{}\t{token}", key.id, key.name);
+        }
+        ["list"] => {
+            for key in list(&pool).await? {
+                let status = match key.revoked {
+                    Some(revoked) => format!("revoked {}", revoked.to_rfc3339()),
+                    None => "active".to_string(),
+                };
+                println!(
+                    "{}\t{}\t{}\t{status}",
+                    key.id,
+                    key.name,
+                    key.scopes.join(",")
+                );
+            }
+        }
+        ["revoke", id] => {
+            if !revoke(id, &pool).await? {
+                anyhow::bail!("No active API key `{id}`");
+            }
+        }
+        _ => anyhow::bail!(
+            "Usage: keys create <name> <scope>... | keys list | keys revoke <id>\n\
+            Scopes: assets:write, collections:read, collections:write, processes:execute, admin"
+        ),
+    }
+
+    Ok(())
+}
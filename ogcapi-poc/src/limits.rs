@@ -0,0 +1,335 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::ConnectInfo,
+    http::{
+        header::{AUTHORIZATION, CONTENT_LENGTH, RETRY_AFTER},
+        HeaderMap, HeaderValue, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultKeyedRateLimiter, Quota, RateLimiter,
+};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+use ogcapi_services::Error;
+
+/// Number of tracked clients before stale buckets are dropped
+const MAX_CLIENTS: usize = 10_000;
+
+/// Client IP if neither the peer address nor a forwarded address is available
+pub(crate) const UNKNOWN: &str = "unknown";
+
+/// Whether a request without client address was logged
+static UNKNOWN_LOGGED: AtomicBool = AtomicBool::new(false);
+
+/// Number of reverse proxies in front of the service (`TRUSTED_PROXIES`)
+static TRUSTED_PROXIES: Lazy<usize> = Lazy::new(|| {
+    std::env::var("TRUSTED_PROXIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+});
+
+/// Rate and request body size limits
+pub(crate) struct Limits {
+    /// Requests per minute and IP without credentials
+    anonymous: Option<DefaultKeyedRateLimiter<String>>,
+    /// Requests per minute and credential or IP with credentials
+    authenticated: Option<DefaultKeyedRateLimiter<String>>,
+    /// Maximum request body size in bytes
+    body: usize,
+    /// Maximum request body size of the asset loading processes in bytes
+    load: usize,
+}
+
+/// Limit from the environment or the default, `0` disables the limit
+fn env(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn limiter(per_minute: u32) -> Option<DefaultKeyedRateLimiter<String>> {
+    NonZeroU32::new(per_minute).map(|n| RateLimiter::keyed(Quota::per_minute(n)))
+}
+
+fn bytes(mib: u32) -> usize {
+    match mib {
+        0 => usize::MAX,
+        mib => mib as usize * 1024 * 1024,
+    }
+}
+
+impl Limits {
+    /// Limits from `RATE_LIMIT_ANONYMOUS`, `RATE_LIMIT_AUTHENTICATED` (requests per
+    /// minute), `BODY_LIMIT` and `BODY_LIMIT_LOAD` (MiB)
+    pub(crate) fn from_env() -> Arc<Self> {
+        match *TRUSTED_PROXIES {
+            0 => tracing::info!(
+                "limiting clients by peer address, set `TRUSTED_PROXIES` behind reverse proxies"
+            ),
+            n => tracing::info!("limiting clients by `X-Forwarded-For` behind {n} trusted proxies"),
+        }
+
+        Arc::new(Limits {
+            anonymous: limiter(env("RATE_LIMIT_ANONYMOUS", 300)),
+            authenticated: limiter(env("RATE_LIMIT_AUTHENTICATED", 1200)),
+            body: bytes(env("BODY_LIMIT", 10)),
+            load: bytes(env("BODY_LIMIT_LOAD", 256)),
+        })
+    }
+}
+
+/// Client IP as seen by the outermost trusted proxy, or the peer address without
+/// proxies, [`UNKNOWN`] if neither is available
+pub(crate) fn client_ip<B>(req: &Request<B>) -> String {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    match forwarded_ip(req.headers(), peer, *TRUSTED_PROXIES) {
+        Some(ip) => ip.to_string(),
+        None => {
+            if !UNKNOWN_LOGGED.swap(true, Ordering::Relaxed) {
+                tracing::warn!(
+                    "client address unknown, check `TRUSTED_PROXIES`, requests without \
+                    address are not limited per IP"
+                );
+            }
+            UNKNOWN.to_string()
+        }
+    }
+}
+
+/// Client IP behind `trusted` proxies, each appending the address it received
+/// the request from to `X-Forwarded-For`, so that entries added by the client
+/// itself are ignored
+fn forwarded_ip(headers: &HeaderMap, peer: Option<IpAddr>, trusted: usize) -> Option<IpAddr> {
+    if trusted == 0 {
+        return peer;
+    }
+
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+
+    if forwarded.is_empty() {
+        // Set by the proxy instead
+        return headers
+            .get("x-real-ip")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|ip| ip.trim().parse().ok());
+    }
+
+    forwarded[forwarded.len().saturating_sub(trusted)]
+        .parse()
+        .ok()
+}
+
+/// Check the token bucket of a client, returns the seconds to wait if exhausted
+fn check(limiter: &Option<DefaultKeyedRateLimiter<String>>, key: String) -> Option<u64> {
+    let limiter = limiter.as_ref()?;
+
+    if limiter.len() > MAX_CLIENTS {
+        limiter.retain_recent();
+    }
+
+    limiter.check_key(&key).err().map(|not_until| {
+        let wait = not_until.wait_time_from(DefaultClock::default().now());
+        wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+    })
+}
+
+/// Apply the rate limits per IP and credential and the body size limit per route
+pub(crate) async fn limit(req: Request<Body>, next: Next<Body>, limits: Arc<Limits>) -> Response {
+    // Rate limits, credentials are hashed to not keep them in memory. Clients
+    // without address are not limited per IP, instead of sharing a single bucket.
+    let ip = client_ip(&req);
+    let ip = (ip != UNKNOWN).then(|| format!("ip:{ip}"));
+    let wait = match req.headers().get(AUTHORIZATION) {
+        Some(credential) => {
+            let credential = format!("{:x}", Sha256::digest(credential.as_bytes()));
+            check(&limits.authenticated, format!("credential:{credential}"))
+                .or_else(|| ip.and_then(|ip| check(&limits.authenticated, ip)))
+        }
+        None => ip.and_then(|ip| check(&limits.anonymous, ip)),
+    };
+
+    if let Some(seconds) = wait {
+        let mut response = Error::Exception(
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("Rate limit exceeded, retry in {seconds} seconds"),
+        )
+        .into_response();
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        return response;
+    }
+
+    // Body size limit
+    let path = req.uri().path();
+    let max = if path.ends_with("/processes/load-asset/execution")
+        || path.ends_with("/processes/load-assets/execution")
+    {
+        limits.load
+    } else {
+        limits.body
+    };
+
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+
+    match length {
+        Some(length) if length > max => too_large(max),
+        Some(_) => next.run(req).await,
+        // Chunked body, count while buffering
+        None => {
+            let (parts, mut body) = req.into_parts();
+            let mut bytes = Vec::new();
+            while let Some(chunk) = body.data().await {
+                match chunk {
+                    Ok(chunk) if bytes.len() + chunk.len() > max => return too_large(max),
+                    Ok(chunk) => bytes.extend_from_slice(&chunk),
+                    Err(e) => {
+                        return Error::Exception(StatusCode::BAD_REQUEST, e.to_string())
+                            .into_response()
+                    }
+                }
+            }
+            next.run(Request::from_parts(parts, Body::from(Bytes::from(bytes))))
+                .await
+        }
+    }
+}
+
+fn too_large(max: usize) -> Response {
+    Error::Exception(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("Request body exceeds {} MiB", max / 1024 / 1024),
+    )
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn headers(entries: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn peer_without_proxies() {
+        let peer = "192.0.2.1".parse().ok();
+        let headers = headers(&[
+            ("x-forwarded-for", "198.51.100.7"),
+            ("x-real-ip", "198.51.100.7"),
+        ]);
+
+        assert_eq!(forwarded_ip(&headers, peer, 0), peer);
+        assert_eq!(forwarded_ip(&headers, None, 0), None);
+    }
+
+    #[test]
+    fn spoofed_forwarded_for() {
+        // The client sent `198.51.100.7`, the proxy appended the actual address
+        let headers = headers(&[("x-forwarded-for", "198.51.100.7, 203.0.113.9")]);
+
+        assert_eq!(forwarded_ip(&headers, None, 1), "203.0.113.9".parse().ok());
+        assert_eq!(forwarded_ip(&headers, None, 2), "198.51.100.7".parse().ok());
+    }
+
+    #[test]
+    fn multiple_proxies() {
+        let headers = headers(&[
+            ("x-forwarded-for", "198.51.100.7, 203.0.113.9"),
+            ("x-forwarded-for", "10.0.0.2"),
+        ]);
+
+        assert_eq!(forwarded_ip(&headers, None, 2), "203.0.113.9".parse().ok());
+        // Fewer entries than proxies, all added by trusted proxies
+        assert_eq!(forwarded_ip(&headers, None, 5), "198.51.100.7".parse().ok());
+    }
+
+    #[test]
+    fn real_ip_behind_proxy() {
+        let headers = headers(&[("x-real-ip", "203.0.113.9")]);
+
+        assert_eq!(forwarded_ip(&headers, None, 1), "203.0.113.9".parse().ok());
+        assert_eq!(forwarded_ip(&headers, None, 0), None);
+    }
+
+    #[test]
+    fn invalid_address() {
+        let headers = headers(&[("x-forwarded-for", "198.51.100.7, not-an-ip")]);
+
+        assert_eq!(forwarded_ip(&headers, None, 1), None);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_per_client() {
+        let limits = Arc::new(Limits {
+            anonymous: limiter(1),
+            authenticated: limiter(1),
+            body: bytes(1),
+            load: bytes(1),
+        });
+        let app = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn(move |req, next| {
+                limit(req, next, limits.clone())
+            }));
+        let status = |peer: Option<&str>| {
+            let mut req = Request::get("/").body(Body::empty()).unwrap();
+            if let Some(peer) = peer {
+                req.extensions_mut()
+                    .insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+            }
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+
+        // Each peer has its own bucket
+        assert_eq!(status(Some("192.0.2.1:40000")).await, StatusCode::OK);
+        assert_eq!(
+            status(Some("192.0.2.1:40001")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(status(Some("192.0.2.2:40000")).await, StatusCode::OK);
+
+        // Clients without address do not share a bucket
+        for _ in 0..3 {
+            assert_eq!(status(None).await, StatusCode::OK);
+        }
+    }
+}
@@ -1,8 +1,8 @@
 use anyhow::Context;
-use aws_sdk_s3::model::ObjectCannedAcl;
+use aws_sdk_s3::{error::HeadObjectError, model::MetadataDirective, types::SdkError};
 use axum::{
     async_trait,
-    http::StatusCode,
+    http::{header::LOCATION, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -12,7 +12,7 @@ use serde_json::{Map, Value};
 use url::Url;
 use uuid::Uuid;
 
-use ogcapi_drivers::s3::ByteStream;
+use ogcapi_drivers::s3::{ByteStream, S3};
 use ogcapi_services::{Error, Processor, Result, State};
 use ogcapi_types::{
     common::Crs,
@@ -21,18 +21,29 @@ use ogcapi_types::{
     stac::Asset,
 };
 
-use crate::{AWS_S3_BUCKET, AWS_S3_BUCKET_BASE};
+use crate::{
+    catalog::{self, Update},
+    media_type,
+    versions::{self, Archive},
+    visibility, AWS_S3_BUCKET, AWS_S3_BUCKET_BASE,
+};
+
+/// Number of retries for conflicting item updates
+const RETRIES: usize = 3;
+
+/// Maximum size of a single server side copy
+const MAX_COPY_SIZE: i64 = 5 * 1024 * 1024 * 1024;
 
 /// STAC Asset loader
 pub(crate) struct AssetLoader;
 
 /// Asset loader input schema
 #[derive(Deserialize, Debug, JsonSchema)]
-struct AssetLoaderInputs {
+pub(crate) struct AssetLoaderInputs {
     /// File to upload
     file: File,
     /// S3 key
-    key: String,
+    pub(crate) key: String,
     /// Optional asset id
     id: Option<String>,
     /// The displayed title for clients and users.
@@ -48,6 +59,11 @@ struct AssetLoaderInputs {
     item: Option<Item>,
     /// Preperties to update
     properties: Option<Properties>,
+    /// Only update the existing Item if its ETag matches (`If-Match` header)
+    #[serde(rename = "ifMatch")]
+    if_match: Option<String>,
+    /// Keep a replaced asset as previous version (defaults to `ASSET_VERSIONING`)
+    versioning: Option<bool>,
 }
 
 #[derive(Deserialize, Debug, JsonSchema)]
@@ -55,9 +71,9 @@ struct File {
     /// File
     value: FileValue,
     // encoding: Option<String>,
-    /// Media Type of the file
+    /// Media Type of the file, detected from the content if not set
     #[serde(rename = "mediaType")]
-    media_type: String,
+    media_type: Option<String>,
 }
 
 #[derive(Deserialize, Debug, JsonSchema)]
@@ -71,6 +87,7 @@ enum FileValue {
 
 #[derive(Deserialize, Debug, JsonSchema)]
 struct FileReference {
+    /// File uri (`http(s)://` or `s3://bucket/key`)
     uri: String,
     method: Method,
 }
@@ -93,7 +110,7 @@ struct Item {
 enum ItemValue {
     /// Existing Item id
     String(String),
-    /// An Item/Feature to create
+    /// An Item/Feature to create, merged into the Item if it exists
     Item(Map<String, Value>),
 }
 #[derive(Deserialize, Debug, JsonSchema)]
@@ -101,9 +118,32 @@ struct Properties {
     value: Map<String, Value>,
 }
 
-/// Asset loader output schema "URI of the crated/updated Item."
+/// Precondition failed error for items changed since the given ETag
+fn modified(id: &str) -> Error {
+    Error::Exception(
+        StatusCode::PRECONDITION_FAILED,
+        format!("Item `{id}` does not match the `If-Match` ETag"),
+    )
+}
+
+/// Asset loader output schema
 #[derive(JsonSchema)]
-struct AssetLoaderOutputs(String);
+#[allow(dead_code)]
+struct AssetLoaderOutputs {
+    /// URI of the created/updated Item
+    location: String,
+    /// The created/updated STAC Item
+    item: Option<Map<String, Value>>,
+}
+
+/// Result encoding requested by the client
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ResponseType {
+    #[default]
+    Raw,
+    Document,
+}
 
 #[async_trait]
 impl Processor for AssetLoader {
@@ -134,54 +174,250 @@ impl Processor for AssetLoader {
     }
 
     async fn execute(&self, execute: Execute, state: &State, url: &Url) -> Result<Response> {
-        let value = serde_json::to_value(execute.inputs).unwrap();
-        let inputs: AssetLoaderInputs = serde_json::from_value(value)
+        let execute = serde_json::to_value(execute).unwrap();
+        let inputs: AssetLoaderInputs = serde_json::from_value(execute["inputs"].to_owned())
             .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
 
-        // Create asset
-        let mut asset = match inputs.file.value {
-            FileValue::Value(v) => {
-                let bytes = base64::decode(v).context("Failed to decode base64 string")?;
-                state
-                    .s3
-                    .client
-                    .put_object()
-                    .bucket(AWS_S3_BUCKET)
-                    .key(&inputs.key)
-                    .body(ByteStream::from(bytes))
-                    .content_type(&inputs.file.media_type)
-                    .acl(ObjectCannedAcl::PublicRead)
-                    .send()
-                    .await
-                    .context("Failed to put object to S3")?;
-
-                Asset::new(format!(
-                    "{}/{}",
-                    AWS_S3_BUCKET_BASE,
-                    inputs.key.trim_start_matches('/')
-                ))
+        // Requested result encoding, defaults to the raw location
+        let response: ResponseType =
+            serde_json::from_value(execute["response"].to_owned()).unwrap_or_default();
+        let outputs: Vec<String> = match execute["outputs"].as_object() {
+            Some(outputs) if !outputs.is_empty() => outputs.keys().cloned().collect(),
+            _ if response == ResponseType::Document => {
+                vec!["location".to_string(), "item".to_string()]
+            }
+            _ => vec!["location".to_string()],
+        };
+
+        // Validate the requested outputs before uploading anything
+        if let Some(output) = outputs
+            .iter()
+            .find(|output| !["location", "item"].contains(&output.as_str()))
+        {
+            return Err(Error::Exception(
+                StatusCode::BAD_REQUEST,
+                format!("Unknown output `{output}`"),
+            ));
+        }
+        if response == ResponseType::Raw && outputs.len() > 1 {
+            return Err(Error::Exception(
+                StatusCode::BAD_REQUEST,
+                "Raw response requires a single output".to_string(),
+            ));
+        }
+        if inputs.if_match.is_some() && inputs.item.is_none() {
+            return Err(Error::Exception(
+                StatusCode::BAD_REQUEST,
+                "`ifMatch` requires an item".to_string(),
+            ));
+        }
+
+        let upload = create_asset(&inputs, state).await?;
+
+        let location = add_asset(inputs, upload, state, url).await?;
+
+        // Collect outputs
+        let mut results = Map::new();
+        for output in &outputs {
+            match output.as_str() {
+                "location" => {
+                    results.insert(output.to_owned(), Value::String(location.url.to_string()));
+                }
+                "item" => {
+                    if let Some(item_id) = &location.item {
+                        let item = state
+                            .drivers
+                            .features
+                            .read_feature(&location.collection, item_id, &Crs::default())
+                            .await?
+                            .ok_or(Error::NotFound)?;
+                        results.insert(output.to_owned(), serde_json::to_value(item).unwrap());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let body = match response {
+            ResponseType::Document => Value::Object(results),
+            ResponseType::Raw => results
+                .into_iter()
+                .next()
+                .map(|(_, v)| v)
+                .unwrap_or_default(),
+        };
+
+        // 201 with location header for created items
+        if location.created {
+            Ok((
+                StatusCode::CREATED,
+                [(LOCATION, location.url.to_string())],
+                Json(body),
+            )
+                .into_response())
+        } else {
+            Ok(Json(body).into_response())
+        }
+    }
+}
+
+/// Item or collection the asset was added to
+pub(crate) struct Location {
+    /// URI of the item/collection
+    pub(crate) url: Url,
+    collection: String,
+    item: Option<String>,
+    /// Whether the item was created
+    created: bool,
+}
+
+/// Asset created from the file input
+pub(crate) struct Upload {
+    asset: Asset,
+    /// Key of a newly created S3 object, removed if the catalog update fails
+    created: Option<String>,
+    /// Replaced asset, recorded in the history once the catalog update succeeded
+    archive: Option<Archive>,
+}
+
+/// Create asset from the file input, uploading the content to S3 if required
+pub(crate) async fn create_asset(inputs: &AssetLoaderInputs, state: &State) -> Result<Upload> {
+    // Validate target before uploading anything
+    check_target(inputs, state).await?;
+
+    let uploads = !matches!(
+        &inputs.file.value,
+        FileValue::Reference(FileReference {
+            method: Method::Link,
+            ..
+        })
+    );
+    let created = if uploads && !object_exists(&inputs.key, &state.s3).await {
+        Some(inputs.key.to_owned())
+    } else {
+        None
+    };
+
+    // Preserve the asset about to be replaced
+    let mut archive = None;
+    if let Some(id) = inputs.id.as_deref() {
+        if inputs.versioning.unwrap_or_else(versions::enabled) {
+            let item = inputs.item.as_ref().and_then(|item| match &item.value {
+                ItemValue::String(id) => Some(id.as_str()),
+                ItemValue::Item(object) => object.get("id").and_then(Value::as_str),
+            });
+            let key = inputs.key.trim_start_matches('/');
+
+            archive = versions::archive(
+                &inputs.collection,
+                item,
+                id,
+                uploads.then_some(key),
+                &state.db.pool,
+                &state.s3,
+            )
+            .await?;
+        }
+    }
+
+    match upload(inputs, state).await {
+        Ok(asset) => Ok(Upload {
+            asset,
+            created,
+            archive,
+        }),
+        Err(e) => {
+            if let Some(archive) = archive {
+                archive.rollback(&state.db.pool, &state.s3).await;
             }
-            FileValue::Reference(reference) => match reference.method {
-                Method::Link => Asset::new(reference.uri),
+            Err(e)
+        }
+    }
+}
+
+/// Upload the file content to S3 if required and create the asset
+async fn upload(inputs: &AssetLoaderInputs, state: &State) -> Result<Asset> {
+    let (mut asset, media_type) = match &inputs.file.value {
+        FileValue::Value(v) => {
+            let bytes = base64::decode(v).context("Failed to decode base64 string")?;
+            let media_type = resolve_media_type(inputs, media_type::sniff(&bytes))?;
+
+            (
+                put_object(inputs, bytes, &media_type, state).await?,
+                media_type,
+            )
+        }
+        FileValue::Reference(reference) if reference.uri.starts_with("s3://") => {
+            let (bucket, source) = reference
+                .uri
+                .trim_start_matches("s3://")
+                .split_once('/')
+                .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+                .ok_or_else(|| {
+                    Error::Exception(
+                        StatusCode::BAD_REQUEST,
+                        format!("Invalid S3 uri `{}`", reference.uri),
+                    )
+                })?;
+
+            match reference.method {
+                Method::Link if bucket != AWS_S3_BUCKET => {
+                    return Err(Error::Exception(
+                        StatusCode::BAD_REQUEST,
+                        format!("Only objects of bucket `{AWS_S3_BUCKET}` can be linked"),
+                    ))
+                }
+                Method::Load if !loadable(bucket) => {
+                    return Err(Error::Exception(
+                        StatusCode::BAD_REQUEST,
+                        format!("Objects of bucket `{bucket}` can not be loaded"),
+                    ))
+                }
+                _ => {}
+            }
+
+            let size = object_size(bucket, source, state)
+                .await
+                .map_err(|e| match e {
+                    SdkError::ServiceError { err, .. } if err.is_not_found() => Error::Exception(
+                        StatusCode::NOT_FOUND,
+                        format!("Object `{}` not found", reference.uri),
+                    ),
+                    SdkError::ServiceError { .. } => Error::Exception(
+                        StatusCode::BAD_REQUEST,
+                        format!("Object `{}` can not be read", reference.uri),
+                    ),
+                    e => anyhow::Error::from(e).into(),
+                })?;
+
+            let detected = media_type::sniff_object(bucket, source, &state.s3).await;
+            let media_type = resolve_media_type(inputs, detected)?;
+
+            let asset = match reference.method {
+                Method::Link => Asset::new(format!("{AWS_S3_BUCKET_BASE}/{source}")),
                 Method::Load => {
-                    let stream = if reference.uri.starts_with("http") {
-                        let resp = reqwest::get(reference.uri).await.expect("request failed");
-                        ByteStream::from(resp.bytes().await.unwrap())
-                    } else {
-                        ByteStream::from_path(reference.uri).await.unwrap()
-                    };
+                    if size > MAX_COPY_SIZE {
+                        return Err(Error::Exception(
+                            StatusCode::BAD_REQUEST,
+                            format!("Object `{}` exceeds the copy limit of 5 GiB", reference.uri),
+                        ));
+                    }
+
+                    // Server side copy, the bytes are not transferred through the service.
+                    // The metadata is replaced to store the resolved media type.
                     state
                         .s3
                         .client
-                        .put_object()
+                        .copy_object()
+                        .copy_source(format!("{bucket}/{source}"))
                         .bucket(AWS_S3_BUCKET)
                         .key(&inputs.key)
-                        .body(stream)
-                        .content_type(&inputs.file.media_type)
-                        .acl(ObjectCannedAcl::PublicRead)
+                        .metadata_directive(MetadataDirective::Replace)
+                        .set_content_type(media_type.to_owned())
+                        .acl(visibility::acl(&inputs.collection, &state.db.pool).await?)
                         .send()
                         .await
-                        .context("Failed to put object to S3")?;
+                        .context("Failed to copy S3 object")?;
 
                     Asset::new(format!(
                         "{}/{}",
@@ -189,69 +425,358 @@ impl Processor for AssetLoader {
                         inputs.key.trim_start_matches('/')
                     ))
                 }
-            },
-        };
+            };
 
-        asset.title = inputs.title;
-        asset.description = inputs.description;
-        asset.r#type = Some(inputs.file.media_type);
-        asset.roles = inputs.roles;
+            (asset, media_type)
+        }
+        FileValue::Reference(reference) => match reference.method {
+            Method::Link => (
+                Asset::new(reference.uri.to_owned()),
+                resolve_media_type(inputs, None)?,
+            ),
+            Method::Load => {
+                // Local paths are not loaded, they would expose files of the service
+                if !reference.uri.starts_with("http://") && !reference.uri.starts_with("https://") {
+                    return Err(Error::Exception(
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "Unsupported uri `{}`, expected `http(s)://` or `s3://`",
+                            reference.uri
+                        ),
+                    ));
+                }
+                let bytes = fetch(&reference.uri).await.map_err(|e| {
+                    Error::Exception(
+                        StatusCode::BAD_REQUEST,
+                        format!("Failed to fetch `{}`: {e}", reference.uri),
+                    )
+                })?;
+                let media_type = resolve_media_type(inputs, media_type::sniff(&bytes))?;
 
-        let key = inputs.id.unwrap_or_else(|| Uuid::new_v4().to_string());
+                (
+                    put_object(inputs, bytes, &media_type, state).await?,
+                    media_type,
+                )
+            }
+        },
+    };
 
-        let location = if let Some(item) = inputs.item {
-            let item_id = match item.value {
-                ItemValue::String(id) => {
-                    let mut item = state
-                        .drivers
-                        .features
-                        .read_feature(&inputs.collection, &id, &Crs::default())
-                        .await?
-                        .expect("existing item");
+    asset.title = inputs.title.to_owned();
+    asset.description = inputs.description.to_owned();
+    asset.r#type = media_type;
+    asset.roles = inputs.roles.to_owned();
 
-                    item.assets.insert(key, asset);
+    Ok(asset)
+}
 
-                    if let Some(properties) = inputs.properties {
-                        item.append_properties(properties.value)
-                    }
+/// Check that the target collection/item exists and a new item is valid
+async fn check_target(inputs: &AssetLoaderInputs, state: &State) -> Result<()> {
+    if state
+        .drivers
+        .collections
+        .read_collection(&inputs.collection)
+        .await?
+        .is_none()
+    {
+        return Err(Error::Exception(
+            StatusCode::NOT_FOUND,
+            format!("Collection `{}` not found", inputs.collection),
+        ));
+    }
 
-                    state.drivers.features.update_feature(&item).await?;
+    match inputs.item.as_ref().map(|item| &item.value) {
+        Some(ItemValue::String(id)) => {
+            if state
+                .drivers
+                .features
+                .read_feature(&inputs.collection, id, &Crs::default())
+                .await?
+                .is_none()
+            {
+                return Err(Error::Exception(
+                    StatusCode::NOT_FOUND,
+                    format!(
+                        "Item `{id}` not found in collection `{}`",
+                        inputs.collection
+                    ),
+                ));
+            }
+        }
+        Some(ItemValue::Item(object)) => {
+            serde_json::from_value::<Feature>(Value::Object(object.to_owned()))
+                .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+        }
+        None => {}
+    }
 
-                    id.to_owned()
+    Ok(())
+}
+
+/// Fetch the content of an http(s) uri
+async fn fetch(uri: &str) -> reqwest::Result<Vec<u8>> {
+    let resp = reqwest::get(uri).await?.error_for_status()?;
+    Ok(resp.bytes().await?.to_vec())
+}
+
+/// Whether objects of the bucket can be loaded, the service bucket and those
+/// listed in `LOAD_BUCKETS` (comma separated)
+fn loadable(bucket: &str) -> bool {
+    bucket == AWS_S3_BUCKET
+        || std::env::var("LOAD_BUCKETS").map_or(false, |buckets| {
+            buckets.split(',').any(|b| b.trim() == bucket)
+        })
+}
+
+/// Size of an S3 object in bytes
+async fn object_size(
+    bucket: &str,
+    key: &str,
+    state: &State,
+) -> std::result::Result<i64, SdkError<HeadObjectError>> {
+    let head = state
+        .s3
+        .client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await?;
+
+    Ok(head.content_length())
+}
+
+/// Whether an object with the given key exists on S3
+pub(crate) async fn object_exists(key: &str, s3: &S3) -> bool {
+    s3.client
+        .head_object()
+        .bucket(AWS_S3_BUCKET)
+        .key(key)
+        .send()
+        .await
+        .is_ok()
+}
+
+/// Put file content to S3
+async fn put_object(
+    inputs: &AssetLoaderInputs,
+    bytes: Vec<u8>,
+    media_type: &Option<String>,
+    state: &State,
+) -> Result<Asset> {
+    state
+        .s3
+        .client
+        .put_object()
+        .bucket(AWS_S3_BUCKET)
+        .key(&inputs.key)
+        .body(ByteStream::from(bytes))
+        .set_content_type(media_type.to_owned())
+        .acl(visibility::acl(&inputs.collection, &state.db.pool).await?)
+        .send()
+        .await
+        .context("Failed to put object to S3")?;
+
+    Ok(Asset::new(format!(
+        "{}/{}",
+        AWS_S3_BUCKET_BASE,
+        inputs.key.trim_start_matches('/')
+    )))
+}
+
+/// Media type of the file, rejecting declarations contradicting the content
+fn resolve_media_type(
+    inputs: &AssetLoaderInputs,
+    detected: Option<&'static str>,
+) -> Result<Option<String>> {
+    match (&inputs.file.media_type, detected) {
+        (Some(declared), Some(detected)) => {
+            if media_type::matches(declared, detected) {
+                Ok(Some(declared.to_owned()))
+            } else {
+                Err(Error::Exception(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Declared media type `{declared}` does not match content of type `{detected}`"
+                    ),
+                ))
+            }
+        }
+        (Some(declared), None) => Ok(Some(declared.to_owned())),
+        (None, Some(detected)) => Ok(Some(media_type::refine(detected, &inputs.key).to_string())),
+        (None, None) => Ok(media_type::from_extension(&inputs.key).map(ToString::to_string)),
+    }
+}
+
+/// Add asset to the target item or collection and return its location,
+/// removing a newly uploaded object again if this fails
+pub(crate) async fn add_asset(
+    inputs: AssetLoaderInputs,
+    upload: Upload,
+    state: &State,
+    url: &Url,
+) -> Result<Location> {
+    match update_catalog(inputs, upload.asset, state, url).await {
+        Ok(location) => {
+            if let Some(archive) = upload.archive {
+                if let Err(e) = archive.commit(&state.db.pool).await {
+                    tracing::error!("failed to record asset version: {e}");
                 }
-                ItemValue::Item(object) => {
-                    let mut item: Feature = serde_json::from_value(object.into())
-                        .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+            }
+            Ok(location)
+        }
+        Err(e) => {
+            if let Some(archive) = upload.archive {
+                archive.rollback(&state.db.pool, &state.s3).await;
+            }
+            match upload.created {
+                Some(key) => Err(with_rollback(e, remove_upload(&key, &state.s3).await)),
+                None => Err(e),
+            }
+        }
+    }
+}
+
+/// Remove a newly uploaded object after a failed catalog update, returns the outcome
+pub(crate) async fn remove_upload(key: &str, s3: &S3) -> String {
+    match s3.delete_object(AWS_S3_BUCKET, key).await {
+        Ok(_) => format!("uploaded object `{key}` was removed"),
+        Err(e) => {
+            tracing::error!("failed to remove object `{key}`: {e}");
+            format!("uploaded object `{key}` could not be removed")
+        }
+    }
+}
+
+/// Append the outcome of a rollback to an error, keeping the status of the failed update
+pub(crate) fn with_rollback(e: Error, rollback: String) -> Error {
+    match e {
+        Error::Exception(status, message) => {
+            Error::Exception(status, format!("{message}, {rollback}"))
+        }
+        e => {
+            tracing::warn!("failed to update catalog: {e}, {rollback}");
+            e
+        }
+    }
+}
+
+async fn update_catalog(
+    inputs: AssetLoaderInputs,
+    asset: Asset,
+    state: &State,
+    url: &Url,
+) -> Result<Location> {
+    let key = inputs.id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let if_match = inputs.if_match.as_deref();
+    let pool = &state.db.pool;
+
+    let location = if let Some(item) = inputs.item {
+        let (item_id, created) = match item.value {
+            ItemValue::String(id) => {
+                let assets = Map::from_iter([(key, serde_json::to_value(asset).unwrap())]);
+                let properties = inputs.properties.map(|p| p.value);
+
+                match catalog::merge_item(
+                    &inputs.collection,
+                    &id,
+                    assets,
+                    properties,
+                    if_match,
+                    pool,
+                )
+                .await?
+                {
+                    Update::Updated => (id, false),
+                    Update::Modified => return Err(modified(&id)),
+                    Update::NotFound => return Err(Error::NotFound),
+                }
+            }
+            ItemValue::Item(object) => {
+                let supplied: Vec<String> = object.keys().cloned().collect();
+                let mut item: Feature = serde_json::from_value(object.into())
+                    .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+                item.assets.insert(key, asset);
+                item.collection = Some(inputs.collection.to_owned());
 
-                    item.assets.insert(key, asset);
-                    item.collection = Some(inputs.collection.to_owned());
+                // Merge the supplied members into an existing item, create it otherwise
+                let value = serde_json::to_value(&item).unwrap();
+                let feature: Map<String, Value> =
+                    ["assets", "properties", "geometry", "bbox", "links"]
+                        .into_iter()
+                        .filter(|member| {
+                            *member == "assets" || supplied.iter().any(|s| s == member)
+                        })
+                        .filter_map(|member| {
+                            Some((member.to_string(), value.get(member)?.to_owned()))
+                        })
+                        .collect();
 
-                    if state
-                        .drivers
-                        .features
-                        .read_feature(
+                let mut retries = RETRIES;
+                loop {
+                    if let Some(id) = &item.id {
+                        match catalog::merge_feature(
                             &inputs.collection,
-                            &item.id.clone().unwrap_or_default(),
-                            &Crs::default(),
+                            id,
+                            &feature,
+                            if_match,
+                            pool,
                         )
                         .await?
-                        .is_some()
-                    {
-                        state.drivers.features.update_feature(&item).await?;
-                        item.id.unwrap()
-                    } else {
-                        state.drivers.features.create_feature(&item).await?
+                        {
+                            Update::Updated => break (id.to_owned(), false),
+                            Update::Modified => return Err(modified(id)),
+                            Update::NotFound => {}
+                        }
                     }
-                }
-            };
 
-            format!("../../collections/{}/items/{}", &inputs.collection, item_id)
-        } else {
-            format!("../../collections/{}", &inputs.collection)
+                    if if_match.is_some() {
+                        return Err(modified(&item.id.unwrap_or_default()));
+                    }
+
+                    match state.drivers.features.create_feature(&item).await {
+                        Ok(id) => break (id, true),
+                        // Item might have been created concurrently, retry merging
+                        Err(e) if item.id.is_some() && retries > 0 => {
+                            tracing::debug!("failed to create item, retrying: {e}");
+                            retries -= 1;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
         };
 
-        let location = url.join(&location).unwrap();
+        Location {
+            url: url
+                .join(&format!(
+                    "../../collections/{}/items/{}",
+                    &inputs.collection, item_id
+                ))
+                .unwrap(),
+            collection: inputs.collection,
+            item: Some(item_id),
+            created,
+        }
+    } else {
+        let assets = Map::from_iter([(key, serde_json::to_value(asset).unwrap())]);
 
-        Ok(Json(location).into_response())
-    }
+        if !catalog::merge_collection(&inputs.collection, assets, pool).await? {
+            return Err(Error::Exception(
+                StatusCode::NOT_FOUND,
+                format!("Collection `{}` not found", inputs.collection),
+            ));
+        }
+
+        Location {
+            url: url
+                .join(&format!("../../collections/{}", &inputs.collection))
+                .unwrap(),
+            collection: inputs.collection,
+            item: None,
+            created: false,
+        }
+    };
+
+    Ok(location)
 }
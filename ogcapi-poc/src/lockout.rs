@@ -0,0 +1,284 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    headers::authorization::{Basic, Credentials},
+    http::{
+        header::{AUTHORIZATION, RETRY_AFTER},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use ogcapi_services::Error;
+
+use crate::limits;
+
+/// Failed attempts per user and source IP and per source IP
+static FAILURES: Lazy<Mutex<HashMap<String, Failures>>> = Lazy::new(Default::default);
+
+/// Failed basic authentications since startup
+static FAILED_LOGINS: AtomicU64 = AtomicU64::new(0);
+
+/// Failures are forgotten after this period without attempts
+const RESET: Duration = Duration::from_secs(3600);
+
+/// Maximum lockout
+const MAX_LOCKOUT: Duration = Duration::from_secs(900);
+
+/// Maximum number of tracked users and source IPs
+const MAX_ENTRIES: usize = 10_000;
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Failed attempts before the lockout starts (`LOGIN_MAX_FAILURES`)
+fn threshold() -> u32 {
+    std::env::var("LOGIN_MAX_FAILURES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(5)
+}
+
+/// Failure keys of a user and source IP. Users are locked out per source IP, so
+/// that failures from one client do not lock out a shared account everywhere.
+/// Requests without client IP are only tracked per user.
+fn keys(user: &str, ip: &str) -> Vec<String> {
+    if ip == limits::UNKNOWN {
+        return vec![format!("user:{user}")];
+    }
+    vec![format!("user:{user}@{ip}"), format!("ip:{ip}")]
+}
+
+/// Remaining lockout of the user and source IP of a basic authorization
+fn remaining(headers: &HeaderMap, ip: &str) -> Option<Duration> {
+    let basic = Basic::decode(headers.get(AUTHORIZATION)?)?;
+    let now = Instant::now();
+
+    let failures = FAILURES.lock().unwrap();
+    keys(basic.username(), ip)
+        .iter()
+        .filter_map(|key| failures.get(key)?.locked_until)
+        .filter(|until| *until > now)
+        .max()
+        .map(|until| until - now)
+}
+
+/// Reject basic authorizations of locked out users or source IPs
+pub(crate) fn check(headers: &HeaderMap, ip: &str) -> Result<(), Response> {
+    match remaining(headers, ip) {
+        Some(remaining) => {
+            let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            let mut response = Error::Exception(
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("Too many failed login attempts, retry in {seconds} seconds"),
+            )
+            .into_response();
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+            Err(response)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Record a failed basic authentication, locking out the user and source IP with
+/// exponential backoff once the threshold is reached
+pub(crate) fn failed(user: &str, ip: &str) {
+    let now = Instant::now();
+    let threshold = threshold();
+
+    FAILED_LOGINS.fetch_add(1, Ordering::Relaxed);
+
+    record(
+        &mut FAILURES.lock().unwrap(),
+        keys(user, ip),
+        now,
+        threshold,
+    );
+
+    tracing::info!(user, ip, "failed login");
+}
+
+/// Count a failure of the keys, locking them out once the threshold is reached
+fn record(
+    failures: &mut HashMap<String, Failures>,
+    keys: Vec<String>,
+    now: Instant,
+    threshold: u32,
+) {
+    prune(failures, now);
+
+    for key in keys {
+        let entry = failures.entry(key.to_owned()).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        entry.count += 1;
+        entry.last = now;
+
+        if entry.count >= threshold {
+            let exponent = (entry.count - threshold).min(16);
+            let lockout = Duration::from_secs(1 << exponent).min(MAX_LOCKOUT);
+            entry.locked_until = Some(now + lockout);
+
+            tracing::warn!(
+                failures = entry.count,
+                "locked out `{key}` for {} seconds",
+                lockout.as_secs()
+            );
+        }
+    }
+}
+
+/// Forget failures after `RESET` without attempts and, beyond `MAX_ENTRIES`,
+/// the least recent ones, entries which are currently locked out last
+fn prune(failures: &mut HashMap<String, Failures>, now: Instant) {
+    failures.retain(|_, f| now - f.last < RESET);
+
+    if failures.len() < MAX_ENTRIES {
+        return;
+    }
+
+    // Evict a tenth at once to not sort on every failure
+    let mut entries: Vec<(bool, Instant, String)> = failures
+        .iter()
+        .map(|(key, f)| {
+            let locked = f.locked_until.map_or(false, |until| until > now);
+            (locked, f.last, key.to_owned())
+        })
+        .collect();
+    entries.sort();
+
+    let excess = failures.len() - MAX_ENTRIES * 9 / 10;
+    for (_, _, key) in entries.into_iter().take(excess) {
+        failures.remove(&key);
+    }
+}
+
+/// Reset the failures of a user and source IP after a successful basic authentication
+pub(crate) fn succeeded(user: &str, ip: &str) {
+    let mut failures = FAILURES.lock().unwrap();
+    for key in keys(user, ip) {
+        failures.remove(&key);
+    }
+}
+
+/// Failed login metrics
+#[derive(Serialize)]
+pub(crate) struct LoginMetrics {
+    /// Failed basic authentications since startup
+    failed_logins: u64,
+    /// Currently locked out users and source IPs
+    locked: Vec<String>,
+}
+
+/// Failed login metrics and current lockouts
+pub(crate) async fn metrics() -> Json<LoginMetrics> {
+    let now = Instant::now();
+    let locked = FAILURES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, f)| f.locked_until.map_or(false, |until| until > now))
+        .map(|(key, _)| key.to_owned())
+        .collect();
+
+    Json(LoginMetrics {
+        failed_logins: FAILED_LOGINS.load(Ordering::Relaxed),
+        locked,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockout_after_threshold() {
+        let mut failures = HashMap::new();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            record(&mut failures, keys("loader", "192.0.2.1"), now, 3);
+        }
+
+        let user = &failures["user:loader@192.0.2.1"];
+        assert_eq!(user.count, 3);
+        assert_eq!(user.locked_until, Some(now + Duration::from_secs(1)));
+        assert!(failures["ip:192.0.2.1"].locked_until.is_some());
+    }
+
+    #[test]
+    fn user_locked_out_per_ip() {
+        let mut failures = HashMap::new();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            record(&mut failures, keys("loader", "192.0.2.1"), now, 3);
+        }
+
+        // The same user from another client is not affected
+        assert!(keys("loader", "192.0.2.2")
+            .iter()
+            .all(|key| !failures.contains_key(key)));
+    }
+
+    #[test]
+    fn unknown_ip_not_tracked() {
+        assert_eq!(keys("loader", "unknown"), vec!["user:loader"]);
+    }
+
+    #[test]
+    fn expired_failures_pruned() {
+        let mut failures = HashMap::new();
+        let start = Instant::now();
+
+        record(&mut failures, keys("loader", "192.0.2.1"), start, 3);
+        record(&mut failures, keys("other", "192.0.2.2"), start + RESET, 3);
+
+        assert_eq!(failures.len(), 2);
+        assert!(failures.contains_key("user:other@192.0.2.2"));
+        assert!(!failures.contains_key("user:loader@192.0.2.1"));
+    }
+
+    #[test]
+    fn size_bounded() {
+        let mut failures = HashMap::new();
+        let now = Instant::now();
+
+        // Source IP locked out for longer than the test, then many distinct users
+        for _ in 0..12 {
+            record(&mut failures, keys("admin", "192.0.2.1"), now, 3);
+        }
+        for i in 0..MAX_ENTRIES {
+            let later = now + Duration::from_millis(i as u64);
+            record(
+                &mut failures,
+                keys(&format!("user{i}"), "unknown"),
+                later,
+                3,
+            );
+        }
+
+        assert!(failures.len() <= MAX_ENTRIES);
+        assert!(failures.contains_key("ip:192.0.2.1"));
+        assert!(failures.contains_key(&format!("user:user{}", MAX_ENTRIES - 1)));
+        assert!(!failures.contains_key("user:user0"));
+    }
+}
@@ -1,18 +1,60 @@
+mod assets;
+mod audit;
 mod auth;
+mod batch;
+mod catalog;
+mod clip;
+mod cog;
+mod ensemble;
+mod grants;
+mod grib;
 mod initialization;
+mod jwt;
+mod keys;
+mod limits;
 mod loader;
+mod lockout;
+mod media_type;
 mod observation;
+mod policy;
 mod proj;
+mod raster;
 mod register;
+mod thumbnail;
+mod timeseries;
+mod users;
+mod versions;
+mod visibility;
+mod zonal;
 
-use axum::{handler::Handler, response::IntoResponse};
+use std::net::SocketAddr;
+
+use axum::{
+    handler::Handler,
+    middleware,
+    response::IntoResponse,
+    routing::{delete, get},
+    Extension,
+};
 use tokio_cron_scheduler::{Job, JobScheduler};
-use tower_http::auth::RequireAuthorizationLayer;
+use tower_http::auth::AsyncRequireAuthorizationLayer;
 
 use ogcapi_services::{Config, ConfigParser, Error, OpenAPI, Service, State};
 use ogcapi_types::common::LandingPage;
 
-use crate::{auth::Auth, loader::AssetLoader};
+use crate::{
+    assets::{AssetDeleter, AssetMover},
+    auth::Auth,
+    batch::AssetBatchLoader,
+    clip::RasterClipper,
+    cog::CogConverter,
+    ensemble::EnsembleStatistics,
+    grib::GribConverter,
+    loader::AssetLoader,
+    thumbnail::ThumbnailCreator,
+    timeseries::TimeSeriesExtractor,
+    zonal::ZonalStatistics,
+};
 
 pub static ROOT: &str = "https://poc.meteoschweiz-poc.swisstopo.cloud/root";
 pub static AWS_S3_BUCKET: &str = "met-oapi-poc";
@@ -29,6 +71,12 @@ async fn main() -> anyhow::Result<()> {
     // setup tracing
     ogcapi_services::telemetry::init();
 
+    // manage api keys, e.g. `ogcapi-poc keys list`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("keys") {
+        return keys::cli(&args[1..]).await;
+    }
+
     // parse config
     let config = Config::parse();
 
@@ -51,18 +99,98 @@ async fn main() -> anyhow::Result<()> {
         .processors(vec![
             Box::new(ogcapi_services::Greeter),
             Box::new(AssetLoader),
+            Box::new(AssetBatchLoader),
+            Box::new(AssetDeleter),
+            Box::new(AssetMover),
+            Box::new(ThumbnailCreator),
+            Box::new(CogConverter),
+            Box::new(GribConverter),
+            Box::new(TimeSeriesExtractor),
+            Box::new(ZonalStatistics),
+            Box::new(RasterClipper),
+            Box::new(EnsembleStatistics),
         ]);
 
+    let pool = state.db.pool.clone();
+    let s3 = state.s3.clone();
+
+    // asset history
+    versions::setup(&pool).await?;
+
+    // api keys, collection grants and audit log
+    keys::setup(&pool).await?;
+    grants::setup(&pool).await?;
+    audit::setup(&pool).await?;
+
     // create service
     let mut service = Service::new_with(&config, state).await;
 
-    // add custom basic auth
+    // asset history routes
+    let history = axum::Router::new()
+        .route(
+            "/collections/:collection_id/assets/:asset_id/versions",
+            get(versions::history),
+        )
+        .route(
+            "/collections/:collection_id/items/:id/assets/:asset_id/versions",
+            get(versions::history),
+        )
+        .layer(Extension(pool.clone()));
+
+    // api key administration routes
+    let admin = axum::Router::new()
+        .route("/admin/keys", get(keys::list_keys).post(keys::create_key))
+        .route("/admin/keys/:id", delete(keys::revoke_key))
+        .route(
+            "/admin/grants",
+            get(grants::list_grants).post(grants::create_grant),
+        )
+        .route("/admin/grants/:id", delete(grants::delete_grant))
+        .route("/admin/audit", get(audit::entries))
+        .route("/admin/logins", get(lockout::metrics))
+        .layer(Extension(pool.clone()));
+
+    // route authorization policy
+    let policy = policy::Policy::load()?;
+
+    // issuer and audience of tokens
+    jwt::check_config()?;
+
+    // rate and request body size limits
+    let limits = limits::Limits::from_env();
+
+    // add custom basic and api key auth, enforce collection grants and visibility,
+    // record mutating requests
     service.router = axum::Router::new()
         .nest(
             "/root/",
             service
                 .router
-                .route_layer(RequireAuthorizationLayer::custom(Auth)),
+                .merge(history)
+                .merge(admin)
+                .route_layer(middleware::from_fn({
+                    let pool = pool.clone();
+                    move |req, next| visibility::filter(req, next, pool.clone(), s3.clone())
+                }))
+                .route_layer(middleware::from_fn({
+                    let pool = pool.clone();
+                    move |req, next| grants::enforce(req, next, pool.clone())
+                }))
+                .route_layer(middleware::from_fn({
+                    let pool = pool.clone();
+                    move |req, next| audit::record(req, next, pool.clone())
+                }))
+                .route_layer(middleware::from_fn({
+                    let pool = pool.clone();
+                    move |req, next| catalog::etag(req, next, pool.clone())
+                }))
+                .route_layer(AsyncRequireAuthorizationLayer::new(Auth::new(
+                    pool.clone(),
+                    policy,
+                )))
+                .layer(middleware::from_fn(move |req, next| {
+                    limits::limit(req, next, limits.clone())
+                })),
         )
         .fallback(handler_404.into_service());
 
@@ -72,19 +200,68 @@ async fn main() -> anyhow::Result<()> {
         Job::new_async("30 1/1 * * * *", |_uuid, _l| {
             Box::pin(async move {
                 tracing::info!("register assets");
-                register::run("mhs-upload").await.unwrap();
+                if let Err(e) = register::run("mhs-upload").await {
+                    tracing::error!("failed to register assets: {e}");
+                }
+            })
+        })
+        .unwrap(),
+    )?;
+    // cron job to remove expired clips
+    sched.add(
+        Job::new_async("0 0 * * * *", |_uuid, _l| {
+            Box::pin(async move {
+                tracing::info!("remove expired clips");
+                if let Err(e) = clip::cleanup().await {
+                    tracing::warn!("failed to remove expired clips: {e}");
+                }
             })
         })
         .unwrap(),
     )?;
     sched.start()?;
 
-    // run service with hyper
-    service.serve().await;
+    // run service with hyper, providing the peer address for rate limits and lockouts
+    let listener = std::net::TcpListener::bind((config.host.as_str(), config.port))?;
+    tracing::info!("listening on http://{}", listener.local_addr()?);
+    axum::Server::from_tcp(listener)?
+        .serve(
+            service
+                .router
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
 
     Ok(())
 }
 
+/// Resolves on Ctrl+C or SIGTERM, to finish pending requests before exiting
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
 pub async fn handler_404() -> impl IntoResponse {
     Error::NotFound
 }
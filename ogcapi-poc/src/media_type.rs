@@ -0,0 +1,304 @@
+use ogcapi_drivers::s3::S3;
+use ogcapi_types::common::media_type::{GEO_JSON, JSON};
+
+pub(crate) static CSV: &str = "text/csv";
+pub(crate) static GRIB2: &str = "application/wmo-grib2";
+pub(crate) static HDF5: &str = "application/x-hdf5";
+pub(crate) static NETCDF: &str = "application/netcdf";
+pub(crate) static TIFF: &str = "image/tiff";
+pub(crate) static XML: &str = "text/xml";
+pub(crate) static ZIP: &str = "application/zip";
+
+/// Number of leading bytes required to detect the media type
+const SNIFF_LEN: usize = 4096;
+
+/// Media type from file extension
+pub(crate) fn from_extension(path: &str) -> Option<&'static str> {
+    match path.rsplit('.').next()?.to_lowercase().as_str() {
+        "json" => Some(JSON),
+        "geojson" => Some(GEO_JSON),
+        "csv" => Some(CSV),
+        "h5" | "hdf5" => Some(HDF5),
+        "nc" => Some(NETCDF),
+        "tif" | "tiff" => Some(TIFF),
+        "cap" | "xml" => Some(XML),
+        "zip" => Some(ZIP),
+        "grib2" | "grb2" => Some(GRIB2),
+        _ => None,
+    }
+}
+
+/// Media type from the magic bytes of a file
+pub(crate) fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    let bytes = &bytes[..bytes.len().min(SNIFF_LEN)];
+
+    // GRIB edition 2
+    if bytes.starts_with(b"GRIB") && bytes.get(7) == Some(&2) {
+        return Some(GRIB2);
+    }
+
+    // HDF5 superblock, which may be at offset 0, 512, 1024 or 2048 (also NetCDF-4)
+    if [0, 512, 1024, 2048]
+        .iter()
+        .any(|&offset| bytes[offset.min(bytes.len())..].starts_with(b"\x89HDF\r\n\x1a\n"))
+    {
+        return Some(HDF5);
+    }
+
+    // NetCDF classic, 64-bit offset and 64-bit data
+    if bytes.starts_with(b"CDF\x01")
+        || bytes.starts_with(b"CDF\x02")
+        || bytes.starts_with(b"CDF\x05")
+    {
+        return Some(NETCDF);
+    }
+
+    // TIFF and BigTIFF, little and big endian
+    if bytes.starts_with(b"II*\x00")
+        || bytes.starts_with(b"MM\x00*")
+        || bytes.starts_with(b"II+\x00")
+        || bytes.starts_with(b"MM\x00+")
+    {
+        return Some(TIFF);
+    }
+
+    // ZIP, including empty archives
+    if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+        return Some(ZIP);
+    }
+
+    // Text formats, skipping byte order mark and leading whitespace
+    let text = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    let text = match text.iter().position(|b| !b.is_ascii_whitespace()) {
+        Some(start) => &text[start..],
+        None => return None,
+    };
+
+    // XML (CAP alerts)
+    if text.starts_with(b"<?xml") || text.starts_with(b"<alert") {
+        return Some(XML);
+    }
+
+    // JSON and GeoJSON, by the `type` member of the top-level object
+    if text.starts_with(b"{") {
+        let geojson = [
+            "FeatureCollection",
+            "Feature",
+            "GeometryCollection",
+            "Point",
+            "MultiPoint",
+            "LineString",
+            "MultiLineString",
+            "Polygon",
+            "MultiPolygon",
+        ];
+        if json_type(text).map_or(false, |t| geojson.contains(&t.as_str())) {
+            return Some(GEO_JSON);
+        }
+        return Some(JSON);
+    }
+
+    if text.starts_with(b"[") {
+        return Some(JSON);
+    }
+
+    None
+}
+
+/// Value of the `type` member of a top-level JSON object, which may be truncated
+fn json_type(text: &[u8]) -> Option<String> {
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < text.len() {
+        match text[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth -= 1,
+            b'"' => {
+                let (key, end) = json_string(text, i)?;
+                i = end;
+
+                // Member name followed by a string value
+                if depth == 1 && key == "type" {
+                    if let Some(value) = trim_start(&text[i..]).strip_prefix(b":") {
+                        let value = trim_start(value);
+                        if !value.starts_with(b"\"") {
+                            return None;
+                        }
+                        return json_string(value, 0).map(|(value, _)| value);
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    None
+}
+
+fn trim_start(text: &[u8]) -> &[u8] {
+    let start = text
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(text.len());
+    &text[start..]
+}
+
+/// JSON string starting at the quote at `start` and the index after its closing quote
+fn json_string(text: &[u8], start: usize) -> Option<(String, usize)> {
+    let mut i = start + 1;
+    while i < text.len() {
+        match text[i] {
+            b'\\' => i += 2,
+            b'"' => {
+                return Some((
+                    String::from_utf8_lossy(&text[start + 1..i]).into_owned(),
+                    i + 1,
+                ))
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Detect media type from the leading bytes of an S3 object
+pub(crate) async fn sniff_object(bucket: &str, key: &str, s3: &S3) -> Option<&'static str> {
+    let resp = s3
+        .client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .range(format!("bytes=0-{}", SNIFF_LEN - 1))
+        .send()
+        .await
+        .ok()?;
+    let bytes = resp.body.collect().await.ok()?.into_bytes();
+
+    sniff(&bytes)
+}
+
+/// Prefer the more specific media type of the extension for ambiguous content,
+/// e.g. NetCDF-4 files which are detected as HDF5
+pub(crate) fn refine(detected: &'static str, path: &str) -> &'static str {
+    match from_extension(path) {
+        Some(extension) if detected != GEO_JSON && matches(extension, detected) => extension,
+        _ => detected,
+    }
+}
+
+/// Whether the declared media type is compatible with the detected one
+pub(crate) fn matches(declared: &str, detected: &str) -> bool {
+    // ignore parameters such as `; application=geotiff`
+    let declared = declared
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    if declared == detected {
+        return true;
+    }
+
+    match detected {
+        d if d == GEO_JSON => declared == JSON,
+        // GeoJSON whose `type` is not within the sniffed bytes
+        d if d == JSON => declared == GEO_JSON,
+        d if d == GRIB2 => declared == "application/x-grib2" || declared == "application/grib",
+        // NetCDF-4 files are HDF5 files
+        d if d == HDF5 => {
+            declared == NETCDF
+                || declared == "application/x-netcdf"
+                || declared == "application/x-hdf"
+        }
+        d if d == NETCDF => declared == "application/x-netcdf",
+        d if d == TIFF => declared == "image/geotiff",
+        d if d == XML => declared == "application/xml" || declared == "application/cap+xml",
+        d if d == ZIP => declared == "application/x-zip-compressed",
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_binary() {
+        assert_eq!(sniff(b"GRIB\x00\x00\x00\x02"), Some(GRIB2));
+        assert_eq!(sniff(b"\x89HDF\r\n\x1a\n"), Some(HDF5));
+        assert_eq!(sniff(b"CDF\x01"), Some(NETCDF));
+        assert_eq!(sniff(b"II*\x00"), Some(TIFF));
+        assert_eq!(sniff(b"PK\x03\x04"), Some(ZIP));
+        assert_eq!(sniff(b"\x00\x01"), None);
+    }
+
+    #[test]
+    fn sniff_text() {
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?>"), Some(XML));
+        assert_eq!(sniff(b"\xef\xbb\xbf  [1, 2]"), Some(JSON));
+        assert_eq!(sniff(b"a;b\n1;2"), None);
+    }
+
+    #[test]
+    fn sniff_geojson() {
+        assert_eq!(
+            sniff(br#"{"type": "FeatureCollection", "features": []}"#),
+            Some(GEO_JSON)
+        );
+        assert_eq!(
+            sniff(br#"{"crs": {"type": "name"}, "type":"Feature", "geometry": null}"#),
+            Some(GEO_JSON)
+        );
+        // truncated after the top-level type
+        assert_eq!(
+            sniff(br#"{"type" : "Point", "coordinates": [7.4, 46."#),
+            Some(GEO_JSON)
+        );
+    }
+
+    #[test]
+    fn sniff_json_mentioning_geojson() {
+        assert_eq!(
+            sniff(br#"{"name": "FeatureCollection", "type": "catalog"}"#),
+            Some(JSON)
+        );
+        assert_eq!(
+            sniff(br#"{"data": {"type": "FeatureCollection"}}"#),
+            Some(JSON)
+        );
+        assert_eq!(
+            sniff(br#"{"note": "escaped \" \"type\": \"Feature\""}"#),
+            Some(JSON)
+        );
+    }
+
+    #[test]
+    fn extension() {
+        assert_eq!(from_extension("a/b.GeoJSON"), Some(GEO_JSON));
+        assert_eq!(from_extension("b.grb2"), Some(GRIB2));
+        assert_eq!(from_extension("b.txt"), None);
+    }
+
+    #[test]
+    fn matches_declared() {
+        assert!(matches(GEO_JSON, GEO_JSON));
+        assert!(matches(JSON, GEO_JSON));
+        assert!(matches(GEO_JSON, JSON));
+        assert!(matches("image/tiff; application=geotiff", TIFF));
+        assert!(matches(NETCDF, HDF5));
+        assert!(!matches(TIFF, JSON));
+        assert!(!matches(HDF5, NETCDF));
+    }
+
+    #[test]
+    fn refine_ambiguous() {
+        assert_eq!(refine(HDF5, "a.nc"), NETCDF);
+        assert_eq!(refine(JSON, "a.geojson"), GEO_JSON);
+        assert_eq!(refine(GEO_JSON, "a.json"), GEO_JSON);
+        assert_eq!(refine(TIFF, "a.json"), TIFF);
+    }
+}
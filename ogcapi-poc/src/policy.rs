@@ -0,0 +1,279 @@
+use axum::http::Method;
+use serde::Deserialize;
+
+use crate::keys::Scope;
+
+/// Default route authorization policy
+static DEFAULT: &str = include_str!("../policy.json");
+
+/// Prefix the service is nested under
+const ROOT: &str = "/root";
+
+/// Access level of a route
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub(crate) enum Access {
+    /// No credentials required, given credentials are still verified
+    Public,
+    /// Valid credentials required
+    Authenticated,
+    /// Credentials with the scope required
+    Scope(Scope),
+}
+
+impl TryFrom<String> for Access {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "public" => Ok(Access::Public),
+            "authenticated" => Ok(Access::Authenticated),
+            scope => Ok(Access::Scope(scope.parse()?)),
+        }
+    }
+}
+
+/// Policy rule, applies to requests matching the methods and path pattern
+#[derive(Deserialize, Debug)]
+struct Rule {
+    /// HTTP methods, any if not set
+    #[serde(default)]
+    methods: Vec<String>,
+    /// Path pattern, `*` matches one and `**` any number of segments
+    path: String,
+    access: Access,
+}
+
+/// Route authorization policy, the first matching rule applies
+#[derive(Debug)]
+pub(crate) struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// Load the policy from the file at `AUTH_POLICY` or the default policy
+    pub(crate) fn load() -> anyhow::Result<Self> {
+        let rules = match std::env::var("AUTH_POLICY").ok().filter(|p| !p.is_empty()) {
+            Some(path) => {
+                tracing::info!("loading authorization policy from `{path}`");
+                serde_json::from_slice(&std::fs::read(path)?)?
+            }
+            None => serde_json::from_str(DEFAULT)?,
+        };
+
+        Ok(Policy { rules })
+    }
+
+    /// Access level of a request.
+    ///
+    /// The `route` is the matched route with parameters (e.g.
+    /// `/processes/:process_id/execution`), the `path` the requested path, both
+    /// with or without the `/root` prefix. Requests not matching any rule require
+    /// the `admin` scope.
+    pub(crate) fn access(&self, method: &Method, route: &str, path: &str) -> Access {
+        let route: Vec<&str> = segments(relative(route));
+        let path: Vec<&str> = segments(relative(path));
+
+        self.rules
+            .iter()
+            .find(|rule| {
+                (rule.methods.is_empty()
+                    || rule
+                        .methods
+                        .iter()
+                        .any(|m| m.eq_ignore_ascii_case(method.as_str())))
+                    && matches(&segments(&rule.path), &route, &path)
+            })
+            .map_or(Access::Scope(Scope::Admin), |rule| rule.access)
+    }
+}
+
+/// Path relative to the root
+fn relative(path: &str) -> &str {
+    match path.strip_prefix(ROOT) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => path,
+    }
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+/// Whether a pattern matches the route, a pattern segment matches a literal
+/// route segment, or the requested segment for route parameters
+fn matches(pattern: &[&str], route: &[&str], path: &[&str]) -> bool {
+    match (pattern.split_first(), route.split_first()) {
+        (None, None) => true,
+        (Some((&"**", rest)), _) => {
+            (0..=route.len()).any(|i| matches(rest, &route[i..], &path[i.min(path.len())..]))
+        }
+        (Some((head, pattern)), Some((segment, route))) => {
+            let requested = path.first().copied().unwrap_or_default();
+            let matched =
+                *head == "*" || head == segment || (segment.starts_with(':') && *head == requested);
+            matched && matches(pattern, route, path.get(1..).unwrap_or_default())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        Policy {
+            rules: serde_json::from_str(DEFAULT).unwrap(),
+        }
+    }
+
+    #[test]
+    fn default_policy() {
+        let policy = policy();
+        let access = |method: Method, route: &str, path: &str| policy.access(&method, route, path);
+
+        let matrix = [
+            // Reading and searching
+            (Method::GET, "/collections", "/collections", Access::Public),
+            (
+                Method::HEAD,
+                "/collections/:collection_id/items/:item_id",
+                "/collections/a/items/b",
+                Access::Public,
+            ),
+            (Method::GET, "/search", "/search", Access::Public),
+            (Method::POST, "/search", "/search", Access::Public),
+            // Process execution
+            (
+                Method::POST,
+                "/processes/:process_id/execution",
+                "/processes/load-asset/execution",
+                Access::Scope(Scope::AssetsWrite),
+            ),
+            (
+                Method::POST,
+                "/processes/:process_id/execution",
+                "/processes/move-asset/execution",
+                Access::Scope(Scope::AssetsWrite),
+            ),
+            (
+                Method::POST,
+                "/processes/:process_id/execution",
+                "/processes/extract-timeseries/execution",
+                Access::Scope(Scope::ProcessesExecute),
+            ),
+            // Transactions
+            (
+                Method::POST,
+                "/collections",
+                "/collections",
+                Access::Scope(Scope::CollectionsWrite),
+            ),
+            (
+                Method::PUT,
+                "/collections/:collection_id",
+                "/collections/a",
+                Access::Scope(Scope::CollectionsWrite),
+            ),
+            (
+                Method::DELETE,
+                "/collections/:collection_id/items/:item_id",
+                "/collections/a/items/b",
+                Access::Scope(Scope::CollectionsWrite),
+            ),
+            // Administration and unknown routes
+            (
+                Method::GET,
+                "/admin/keys",
+                "/admin/keys",
+                Access::Scope(Scope::Admin),
+            ),
+            (
+                Method::DELETE,
+                "/admin/grants/:id",
+                "/admin/grants/1",
+                Access::Scope(Scope::Admin),
+            ),
+            (
+                Method::PUT,
+                "/search",
+                "/search",
+                Access::Scope(Scope::Admin),
+            ),
+            (
+                Method::POST,
+                "/processes/:process_id",
+                "/processes/load-asset",
+                Access::Scope(Scope::Admin),
+            ),
+        ];
+
+        for (method, route, path, expected) in matrix {
+            assert_eq!(
+                access(method.clone(), route, path),
+                expected,
+                "{method} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn route_parameters_match_requested_segment() {
+        let rules = r#"[{"path": "/processes/load-asset/execution", "access": "public"}]"#;
+        let policy = Policy {
+            rules: serde_json::from_str(rules).unwrap(),
+        };
+
+        let route = "/processes/:process_id/execution";
+        assert_eq!(
+            policy.access(&Method::POST, route, "/processes/load-asset/execution"),
+            Access::Public
+        );
+        assert_eq!(
+            policy.access(&Method::POST, route, "/processes/clip-raster/execution"),
+            Access::Scope(Scope::Admin)
+        );
+    }
+
+    #[test]
+    fn root_prefix() {
+        let policy = policy();
+
+        assert_eq!(
+            policy.access(&Method::GET, "/root/search", "/root/search"),
+            Access::Public
+        );
+        assert_eq!(
+            policy.access(
+                &Method::GET,
+                "/root/collections/:collection_id/items",
+                "/root/collections/x/items"
+            ),
+            Access::Public
+        );
+        assert_eq!(
+            policy.access(
+                &Method::POST,
+                "/root/collections/:collection_id/items",
+                "/root/collections/x/items"
+            ),
+            Access::Scope(Scope::CollectionsWrite)
+        );
+        assert_eq!(
+            policy.access(&Method::GET, "/root/admin/keys", "/root/admin/keys"),
+            Access::Scope(Scope::Admin)
+        );
+        // Only whole segments are stripped
+        assert_eq!(
+            policy.access(&Method::POST, "/rootsearch", "/rootsearch"),
+            Access::Scope(Scope::Admin)
+        );
+    }
+
+    #[test]
+    fn invalid_scope() {
+        let rules = r#"[{"path": "/**", "access": "everything"}]"#;
+        assert!(serde_json::from_str::<Vec<Rule>>(rules).is_err());
+    }
+}
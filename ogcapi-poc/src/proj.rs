@@ -1,10 +1,148 @@
+use anyhow::Context;
+use geo::BoundingRect;
+
 // New type wrapper around Proj which implements `Send`
 pub(crate) struct Proj(pub(crate) proj::Proj);
 
 impl Proj {
-    pub(crate) fn new(from: &str, to: &str) -> Self {
-        Proj(proj::Proj::new_known_crs(from, to, None).unwrap())
+    pub(crate) fn new(from: &str, to: &str) -> anyhow::Result<Self> {
+        proj::Proj::new_known_crs(from, to, None)
+            .map(Proj)
+            .context("Unsupported coordinate transformation")
     }
 }
 
 unsafe impl Send for Proj {}
+
+/// Supported CRS of loaded features
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SourceCrs {
+    /// WGS 84
+    Wgs84,
+    /// Swiss LV03
+    Lv03,
+    /// Swiss LV95
+    Lv95,
+}
+
+impl SourceCrs {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            SourceCrs::Wgs84 => "EPSG:4326",
+            SourceCrs::Lv03 => "EPSG:21781",
+            SourceCrs::Lv95 => "EPSG:2056",
+        }
+    }
+
+    /// Parse a CRS identifier like `EPSG:2056`, `urn:ogc:def:crs:EPSG::2056`,
+    /// `http://www.opengis.net/def/crs/EPSG/0/2056` or `CRS84`
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        let name = name.trim().trim_end_matches('/');
+        if name.ends_with("CRS84") {
+            return Some(SourceCrs::Wgs84);
+        }
+        if !name.contains("EPSG") {
+            return None;
+        }
+        match name.rsplit(|c| c == ':' || c == '/').next()? {
+            "4326" => Some(SourceCrs::Wgs84),
+            "21781" => Some(SourceCrs::Lv03),
+            "2056" => Some(SourceCrs::Lv95),
+            _ => None,
+        }
+    }
+
+    /// Area of use as `[min x, min y, max x, max y]` in coordinates of the CRS
+    fn bounds(&self) -> [f64; 4] {
+        match self {
+            SourceCrs::Wgs84 => [-180.0, -90.0, 180.0, 90.0],
+            SourceCrs::Lv03 => [485_000.0, 75_000.0, 834_000.0, 300_000.0],
+            SourceCrs::Lv95 => [2_485_000.0, 1_075_000.0, 2_834_000.0, 1_300_000.0],
+        }
+    }
+
+    /// Whether the geometry lies within the area of use, empty geometries do not
+    pub(crate) fn contains(&self, geom: &geo::Geometry) -> bool {
+        let [min_x, min_y, max_x, max_y] = self.bounds();
+        geom.bounding_rect().map_or(false, |rect| {
+            rect.min().x >= min_x
+                && rect.min().y >= min_y
+                && rect.max().x <= max_x
+                && rect.max().y <= max_y
+        })
+    }
+
+    /// Transformation to WGS 84, `None` if already in WGS 84
+    pub(crate) fn to_wgs84(&self) -> anyhow::Result<Option<Proj>> {
+        match self {
+            SourceCrs::Wgs84 => Ok(None),
+            crs => Proj::new(crs.code(), "EPSG:4326").map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{point, polygon, Geometry, GeometryCollection, LineString, MultiPoint};
+
+    use super::*;
+
+    #[test]
+    fn parse() {
+        for name in [
+            "EPSG:2056",
+            "urn:ogc:def:crs:EPSG::2056",
+            "http://www.opengis.net/def/crs/EPSG/0/2056",
+            " http://www.opengis.net/def/crs/EPSG/0/2056/ ",
+        ] {
+            assert_eq!(SourceCrs::parse(name), Some(SourceCrs::Lv95), "{name}");
+        }
+        assert_eq!(SourceCrs::parse("EPSG:21781"), Some(SourceCrs::Lv03));
+        assert_eq!(SourceCrs::parse("EPSG:4326"), Some(SourceCrs::Wgs84));
+        assert_eq!(
+            SourceCrs::parse("urn:ogc:def:crs:OGC:1.3:CRS84"),
+            Some(SourceCrs::Wgs84)
+        );
+        assert_eq!(
+            SourceCrs::parse("http://www.opengis.net/def/crs/OGC/1.3/CRS84"),
+            Some(SourceCrs::Wgs84)
+        );
+
+        assert_eq!(SourceCrs::parse("EPSG:3857"), None);
+        assert_eq!(SourceCrs::parse("2056"), None);
+        assert_eq!(SourceCrs::parse(""), None);
+    }
+
+    #[test]
+    fn within_area_of_use() {
+        let bern = Geometry::Point(point!(x: 2_600_000.0, y: 1_200_000.0));
+        assert!(SourceCrs::Lv95.contains(&bern));
+        assert!(!SourceCrs::Lv03.contains(&bern));
+        assert!(SourceCrs::Wgs84.contains(&Geometry::Point(point!(x: 7.44, y: 46.95))));
+
+        // LV03 coordinates mistaken for LV95
+        let polygon = Geometry::Polygon(polygon![
+            (x: 600_000.0, y: 200_000.0),
+            (x: 601_000.0, y: 200_000.0),
+            (x: 601_000.0, y: 201_000.0),
+        ]);
+        assert!(SourceCrs::Lv03.contains(&polygon));
+        assert!(!SourceCrs::Lv95.contains(&polygon));
+
+        // Partially outside
+        let line = Geometry::LineString(LineString::from(vec![(7.0, 46.0), (181.0, 46.0)]));
+        assert!(!SourceCrs::Wgs84.contains(&line));
+    }
+
+    #[test]
+    fn empty_geometries_invalid() {
+        let empty = [
+            Geometry::MultiPoint(MultiPoint::<f64>(Vec::new())),
+            Geometry::LineString(LineString::<f64>(Vec::new())),
+            Geometry::GeometryCollection(GeometryCollection::<f64>(Vec::new())),
+        ];
+        for geom in empty {
+            assert!(!SourceCrs::Wgs84.contains(&geom));
+        }
+    }
+}
@@ -0,0 +1,504 @@
+use std::path::Path;
+
+use anyhow::Context;
+use gdal::{
+    raster::{Buffer, RasterCreationOption},
+    spatial_ref::SpatialRef,
+    Dataset, Driver, Metadata,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use ogcapi_drivers::s3::S3;
+
+use crate::{assets::s3_key, proj::Proj, AWS_S3_BUCKET};
+
+/// Swiss radar composite grid (CCS4), used if a file lacks georeferencing
+const CCS4_EPSG: u32 = 21781;
+const CCS4_SIZE: (usize, usize) = (710, 640);
+const CCS4_GEO_TRANSFORM: [f64; 6] = [255000.0, 1000.0, 0.0, 480000.0, 0.0, -1000.0];
+
+/// Supported CRS of outputs and query points
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+pub(crate) enum TargetCrs {
+    /// WGS 84
+    #[default]
+    #[serde(rename = "EPSG:4326")]
+    Wgs84,
+    /// Swiss LV95
+    #[serde(rename = "EPSG:2056")]
+    Lv95,
+}
+
+impl TargetCrs {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            TargetCrs::Wgs84 => "EPSG:4326",
+            TargetCrs::Lv95 => "EPSG:2056",
+        }
+    }
+}
+
+/// Output file format
+enum Format {
+    Cog,
+    NetCdf,
+}
+
+impl Format {
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Cog => "tif",
+            Format::NetCdf => "nc",
+        }
+    }
+}
+
+/// Raster band selection
+pub(crate) enum Band {
+    /// Band index, starting at 1
+    Index(isize),
+    /// First band of a GRIB message with the given element (parameter), e.g. `T`
+    Element(String),
+    /// First band of a GRIB message with the given element and level, e.g. `T`
+    /// and `2000-HTGL`
+    Level(String, String),
+}
+
+impl From<isize> for Band {
+    fn from(index: isize) -> Self {
+        Band::Index(index)
+    }
+}
+
+/// Single raster band read into memory
+pub(crate) struct Raster {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    /// Values row by row, top to bottom
+    pub(crate) data: Vec<f64>,
+    pub(crate) no_data: Option<f64>,
+    /// GDAL geo transform
+    pub(crate) geo_transform: [f64; 6],
+    /// Spatial reference (WKT)
+    pub(crate) projection: String,
+}
+
+impl Raster {
+    /// Read a band of a raster file (GeoTIFF, HDF5, GRIB2, ...)
+    pub(crate) async fn from_bytes(bytes: Vec<u8>, band: impl Into<Band>) -> anyhow::Result<Self> {
+        let band = band.into();
+        tokio::task::spawn_blocking(move || {
+            // Some drivers (HDF5) cannot read from virtual memory files
+            let path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+            std::fs::write(&path, bytes)?;
+
+            let raster = Raster::read(&path, &band);
+
+            std::fs::remove_file(&path)?;
+
+            raster
+        })
+        .await?
+    }
+
+    fn read(path: &Path, band: &Band) -> anyhow::Result<Self> {
+        let mut dataset = Dataset::open(path).context("Failed to open raster")?;
+
+        // Containers such as HDF5 expose their rasters as subdatasets
+        if dataset.raster_count() == 0 {
+            let name = dataset
+                .metadata_domain("SUBDATASETS")
+                .unwrap_or_default()
+                .into_iter()
+                .find_map(|entry| {
+                    entry
+                        .split_once('=')
+                        .filter(|(key, _)| key.ends_with("_NAME"))
+                        .map(|(_, name)| name.to_owned())
+                })
+                .context("No raster found")?;
+
+            dataset = Dataset::open(Path::new(&name)).context("Failed to open subdataset")?;
+        }
+
+        let band = match band {
+            Band::Index(index) => *index,
+            Band::Element(element) => find_band(&dataset, element, None)?,
+            Band::Level(element, level) => find_band(&dataset, element, Some(level))?,
+        };
+
+        let (width, height) = dataset.raster_size();
+        let rasterband = dataset.rasterband(band)?;
+        let buffer = rasterband.read_as::<f64>((0, 0), (width, height), (width, height), None)?;
+        let mut no_data = rasterband.no_data_value();
+
+        // ODIM HDF5 scaling of raw values
+        let odim = |name: &str| {
+            dataset
+                .metadata_domain("")
+                .unwrap_or_default()
+                .into_iter()
+                .find_map(|entry| {
+                    entry
+                        .split_once('=')
+                        .filter(|(key, _)| key.ends_with(name))
+                        .and_then(|(_, value)| value.trim().parse::<f64>().ok())
+                })
+        };
+        let mut data = buffer.data;
+        if let (Some(gain), Some(offset)) = (odim("what_gain"), odim("what_offset")) {
+            let missing = [odim("what_nodata"), odim("what_undetect"), no_data];
+            for value in data.iter_mut() {
+                *value = if missing.contains(&Some(*value)) {
+                    f64::NAN
+                } else {
+                    *value * gain + offset
+                };
+            }
+            no_data = Some(f64::NAN);
+        }
+
+        let (geo_transform, projection) = match dataset.geo_transform() {
+            Ok(geo_transform) if geo_transform != [0.0, 1.0, 0.0, 0.0, 0.0, 1.0] => {
+                (geo_transform, dataset.projection())
+            }
+            _ if (width, height) == CCS4_SIZE => (
+                CCS4_GEO_TRANSFORM,
+                SpatialRef::from_epsg(CCS4_EPSG)?.to_wkt()?,
+            ),
+            _ => anyhow::bail!("Raster is not georeferenced"),
+        };
+
+        Ok(Raster {
+            width,
+            height,
+            data,
+            no_data,
+            geo_transform,
+            projection,
+        })
+    }
+
+    /// Write the raster as Cloud Optimized GeoTIFF (float32, deflate compressed)
+    pub(crate) async fn into_cog(self) -> anyhow::Result<Vec<u8>> {
+        self.encode(Format::Cog).await
+    }
+
+    /// Write the raster as NetCDF (float32)
+    pub(crate) async fn into_netcdf(self) -> anyhow::Result<Vec<u8>> {
+        self.encode(Format::NetCdf).await
+    }
+
+    async fn encode(self, format: Format) -> anyhow::Result<Vec<u8>> {
+        tokio::task::spawn_blocking(move || {
+            let path =
+                std::env::temp_dir().join(format!("{}.{}", Uuid::new_v4(), format.extension()));
+
+            let result = self
+                .write(&path, &format)
+                .and_then(|_| std::fs::read(&path).map_err(Into::into));
+
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+
+            result
+        })
+        .await?
+    }
+
+    fn write(&self, path: &Path, format: &Format) -> anyhow::Result<()> {
+        let size = (self.width, self.height);
+
+        let mut dataset = Driver::get("MEM")?.create_with_band_type::<f32, _>(
+            "",
+            self.width as isize,
+            self.height as isize,
+            1,
+        )?;
+        dataset.set_geo_transform(&self.geo_transform)?;
+        if !self.projection.is_empty() {
+            dataset.set_spatial_ref(&SpatialRef::from_wkt(&self.projection)?)?;
+        }
+
+        let data = self
+            .data
+            .iter()
+            .map(|v| {
+                if self.is_valid(*v) {
+                    *v as f32
+                } else {
+                    f32::NAN
+                }
+            })
+            .collect();
+
+        let mut band = dataset.rasterband(1)?;
+        band.set_no_data_value(f64::NAN)?;
+        band.write((0, 0), size, &Buffer::new(size, data))?;
+
+        let (driver, options) = match format {
+            Format::Cog => (
+                "COG",
+                vec![
+                    RasterCreationOption {
+                        key: "COMPRESS",
+                        value: "DEFLATE",
+                    },
+                    RasterCreationOption {
+                        key: "PREDICTOR",
+                        value: "YES",
+                    },
+                ],
+            ),
+            Format::NetCdf => ("netCDF", Vec::new()),
+        };
+        dataset
+            .create_copy(&Driver::get(driver)?, path, &options)
+            .with_context(|| format!("Failed to write {driver}"))?;
+
+        Ok(())
+    }
+
+    /// Reproject to `crs` (e.g. `EPSG:4326`) with nearest neighbour resampling,
+    /// keeping the number of pixels
+    pub(crate) async fn reproject(self, crs: String) -> anyhow::Result<Self> {
+        tokio::task::spawn_blocking(move || self.warp(&crs)).await?
+    }
+
+    fn warp(&self, crs: &str) -> anyhow::Result<Self> {
+        if self.projection.is_empty() {
+            anyhow::bail!("Raster has no spatial reference");
+        }
+
+        let forward = Proj::new(&self.projection, crs)?;
+        let inverse = Proj::new(crs, &self.projection)?;
+
+        // Bounds in the target CRS from the raster outline
+        let mut outline = Vec::with_capacity(2 * (self.width + self.height) + 4);
+        for col in 0..=self.width {
+            outline.push((col as f64, 0.0));
+            outline.push((col as f64, self.height as f64));
+        }
+        for row in 0..=self.height {
+            outline.push((0.0, row as f64));
+            outline.push((self.width as f64, row as f64));
+        }
+
+        let gt = &self.geo_transform;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        );
+        for (col, row) in outline {
+            let point = (
+                gt[0] + col * gt[1] + row * gt[2],
+                gt[3] + col * gt[4] + row * gt[5],
+            );
+            if let Ok((x, y)) = forward.0.convert(point) {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+        if !(min_x.is_finite() && min_y.is_finite() && max_x.is_finite() && max_y.is_finite()) {
+            anyhow::bail!("Raster cannot be transformed to `{crs}`");
+        }
+
+        let (width, height) = (self.width, self.height);
+        let geo_transform = [
+            min_x,
+            (max_x - min_x) / width as f64,
+            0.0,
+            max_y,
+            0.0,
+            -(max_y - min_y) / height as f64,
+        ];
+
+        // Nearest neighbour lookup of the target pixel centers
+        let mut data = vec![f64::NAN; width * height];
+        for row in 0..height {
+            for col in 0..width {
+                let x = geo_transform[0] + (col as f64 + 0.5) * geo_transform[1];
+                let y = geo_transform[3] + (row as f64 + 0.5) * geo_transform[5];
+                if let Ok((x, y)) = inverse.0.convert((x, y)) {
+                    if let Some(value) = self.value_at(x, y) {
+                        data[row * width + col] = value;
+                    }
+                }
+            }
+        }
+
+        Ok(Raster {
+            width,
+            height,
+            data,
+            no_data: Some(f64::NAN),
+            geo_transform,
+            projection: SpatialRef::from_definition(crs)?.to_wkt()?,
+        })
+    }
+
+    /// Subset to the pixels intersecting `bbox` (`[min_x, min_y, max_x, max_y]` in `crs`)
+    pub(crate) async fn clip(self, bbox: [f64; 4], crs: String) -> anyhow::Result<Self> {
+        tokio::task::spawn_blocking(move || self.subset(bbox, &crs)).await?
+    }
+
+    fn subset(&self, bbox: [f64; 4], crs: &str) -> anyhow::Result<Self> {
+        if self.projection.is_empty() {
+            anyhow::bail!("Raster has no spatial reference");
+        }
+        let proj = Proj::new(crs, &self.projection)?;
+
+        // Bounds in the raster CRS from points along the bbox outline
+        let (mut min_col, mut min_row, mut max_col, mut max_row) = (
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        );
+        let gt = &self.geo_transform;
+        let steps = 16;
+        for i in 0..=steps {
+            let t = i as f64 / steps as f64;
+            let x = bbox[0] + t * (bbox[2] - bbox[0]);
+            let y = bbox[1] + t * (bbox[3] - bbox[1]);
+            for point in [(x, bbox[1]), (x, bbox[3]), (bbox[0], y), (bbox[2], y)] {
+                if let Ok((x, y)) = proj.0.convert(point) {
+                    let col = (x - gt[0]) / gt[1];
+                    let row = (y - gt[3]) / gt[5];
+                    min_col = min_col.min(col);
+                    max_col = max_col.max(col);
+                    min_row = min_row.min(row);
+                    max_row = max_row.max(row);
+                }
+            }
+        }
+
+        let col_start = min_col.floor().max(0.0) as usize;
+        let row_start = min_row.floor().max(0.0) as usize;
+        let col_end = (max_col.ceil().max(0.0) as usize).min(self.width);
+        let row_end = (max_row.ceil().max(0.0) as usize).min(self.height);
+
+        if !(min_col.is_finite() && min_row.is_finite())
+            || col_start >= col_end
+            || row_start >= row_end
+        {
+            anyhow::bail!("Bounding box does not intersect the raster");
+        }
+
+        let width = col_end - col_start;
+        let height = row_end - row_start;
+        let mut data = Vec::with_capacity(width * height);
+        for row in row_start..row_end {
+            data.extend_from_slice(
+                &self.data[row * self.width + col_start..row * self.width + col_end],
+            );
+        }
+
+        let mut geo_transform = self.geo_transform;
+        geo_transform[0] = gt[0] + col_start as f64 * gt[1] + row_start as f64 * gt[2];
+        geo_transform[3] = gt[3] + col_start as f64 * gt[4] + row_start as f64 * gt[5];
+
+        Ok(Raster {
+            width,
+            height,
+            data,
+            no_data: self.no_data,
+            geo_transform,
+            projection: self.projection.to_owned(),
+        })
+    }
+
+    /// Value of the pixel containing `(x, y)` in the raster CRS
+    pub(crate) fn value_at(&self, x: f64, y: f64) -> Option<f64> {
+        // Invert the (affine) geo transform
+        let gt = &self.geo_transform;
+        let det = gt[1] * gt[5] - gt[2] * gt[4];
+        let (dx, dy) = (x - gt[0], y - gt[3]);
+        let col = ((gt[5] * dx - gt[2] * dy) / det).floor();
+        let row = ((gt[1] * dy - gt[4] * dx) / det).floor();
+
+        if col < 0.0 || row < 0.0 || col >= self.width as f64 || row >= self.height as f64 {
+            return None;
+        }
+
+        Some(self.data[row as usize * self.width + col as usize]).filter(|v| self.is_valid(*v))
+    }
+
+    /// Value at the point `(x, y)` given in `crs`
+    pub(crate) async fn sample(
+        self,
+        crs: String,
+        point: (f64, f64),
+    ) -> anyhow::Result<Option<f64>> {
+        tokio::task::spawn_blocking(move || {
+            if self.projection.is_empty() {
+                anyhow::bail!("Raster has no spatial reference");
+            }
+            let (x, y) = Proj::new(&crs, &self.projection)?.0.convert(point)?;
+
+            Ok(self.value_at(x, y))
+        })
+        .await?
+    }
+
+    /// Whether a value is valid data
+    pub(crate) fn is_valid(&self, value: f64) -> bool {
+        !value.is_nan()
+            && !self
+                .no_data
+                .map(|no_data| value == no_data)
+                .unwrap_or_default()
+    }
+}
+
+/// Index of the first GRIB message band with the given element (and level)
+fn find_band(dataset: &Dataset, element: &str, level: Option<&String>) -> anyhow::Result<isize> {
+    (1..=dataset.raster_count())
+        .find(|i| {
+            let band = match dataset.rasterband(*i) {
+                Ok(band) => band,
+                Err(_) => return false,
+            };
+            let matches = |key: &str, value: &str| {
+                band.metadata_item(key, "")
+                    .map(|v| v.eq_ignore_ascii_case(value))
+                    .unwrap_or_default()
+            };
+            matches("GRIB_ELEMENT", element)
+                && level.map_or(true, |level| matches("GRIB_SHORT_NAME", level))
+        })
+        .with_context(|| match level {
+            Some(level) => format!("Parameter `{element}` at level `{level}` not found"),
+            None => format!("Parameter `{element}` not found"),
+        })
+}
+
+/// Read the content of an asset, from S3 if it resides on the bucket
+pub(crate) async fn fetch(href: &str, s3: &S3) -> anyhow::Result<Vec<u8>> {
+    match s3_key(href) {
+        Some(key) => {
+            let resp = s3
+                .client
+                .get_object()
+                .bucket(AWS_S3_BUCKET)
+                .key(key)
+                .send()
+                .await
+                .with_context(|| format!("Failed to get object `{key}`"))?;
+            Ok(resp.body.collect().await?.into_bytes().to_vec())
+        }
+        None => Ok(reqwest::get(href)
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec()),
+    }
+}
@@ -1,26 +1,51 @@
-use std::path::Path;
+use std::{collections::HashMap, panic::AssertUnwindSafe, path::Path};
 
-use anyhow::bail;
-use aws_sdk_s3::model::ObjectCannedAcl;
+use anyhow::{anyhow, bail, Context};
+use aws_sdk_s3::model::{Object, ObjectCannedAcl};
 use aws_smithy_types_convert::date_time::DateTimeExt;
 use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{stream, FutureExt, StreamExt};
 use geo::Transform;
-use serde_json::json;
-use tokio_stream::StreamExt;
+use serde_json::{json, Map, Value};
 
 use ogcapi_drivers::{postgres::Db, s3::S3, CollectionTransactions, FeatureTransactions};
 use ogcapi_types::{
-    common::{
-        media_type::{GEO_JSON, JSON},
-        Crs,
-    },
+    common::{media_type::GEO_JSON, Crs},
     stac::Asset,
 };
 
-use crate::{AWS_S3_BUCKET, AWS_S3_BUCKET_BASE, ROOT};
+use crate::{
+    catalog::{self, Update},
+    cog, ensemble, grib, media_type,
+    proj::SourceCrs,
+    thumbnail, versions, visibility, AWS_S3_BUCKET, AWS_S3_BUCKET_BASE, ROOT,
+};
+
+/// Number of retries for conflicting item updates
+const RETRIES: usize = 3;
+
+/// Collections for which missing items are created on registration
+const AUTO_CREATE: [&str; 2] = [
+    "4ccc5153-cc27-47b8-abee-9d6e12e19701",
+    "35ff8133-364a-47eb-a145-0d641b706bff",
+];
+
+/// Radar collections, thumbnails of raster assets are rendered and HDF5 assets
+/// converted to COG (if `RADAR_COG` is set)
+const RADAR: [&str; 3] = [
+    "e2e5132c-85df-417a-8706-f75068d4937e",
+    "e74c17ea-0822-44db-bef9-f37135a68245",
+    "7880287e-5d4b-4e15-b13f-846df89979a3",
+];
+
+/// Number of objects registered concurrently
+const CONCURRENCY: usize = 8;
 
 pub(crate) async fn run(prefix: &str) -> anyhow::Result<()> {
     let now = Utc::now();
+    let radar_cog = std::env::var("RADAR_COG").unwrap_or_else(|_| "false".to_string()) == "true";
+    let cosmo_ensemble =
+        std::env::var("COSMO_ENSEMBLE").unwrap_or_else(|_| "false".to_string()) == "true";
     // Setup drivers
     let db = Db::new().await?;
     let s3 = S3::new().await;
@@ -34,147 +59,312 @@ pub(crate) async fn run(prefix: &str) -> anyhow::Result<()> {
         .into_paginator()
         .send();
 
+    let mut failed = 0;
     while let Some(resp) = paginator.next().await {
-        // Register assets
-        for object in resp.unwrap().contents().unwrap_or_default() {
-            // Last modified
-            let mut datetime = object.last_modified.unwrap().to_chrono_utc();
-            let age = now - datetime;
-
-            // Source key
-            let source = object.key().unwrap_or_default();
-            if source.is_empty()
-                || source.ends_with('/')
-                || source.contains("/.")
-                // || (prefix.is_empty() && source.starts_with("mhs-upload"))
-                || (prefix.is_empty() && source.starts_with("a6296aa9-d183-45c3-90fc-f03ec7d637be"))
-                || (!prefix.is_empty() && age.num_seconds() < 10)
-                || (!prefix.is_empty() && age.num_seconds() >= 70 && age.num_minutes() % 5 != 0)
-            {
-                continue;
-            }
+        let resp = resp.context("Failed to list objects")?;
+
+        // Register assets concurrently, failures (and panics) are reported per object
+        failed += stream::iter(resp.contents().unwrap_or_default())
+            .map(|object| {
+                let key = object.key().unwrap_or_default();
+                let registration =
+                    register(object, prefix, &now, radar_cog, cosmo_ensemble, &db, &s3);
+                AssertUnwindSafe(registration)
+                    .catch_unwind()
+                    .map(move |result| match result {
+                        Ok(Ok(())) => 0,
+                        Ok(Err(e)) => {
+                            tracing::warn!("failed to register `{key}`: {e}");
+                            1
+                        }
+                        Err(_) => {
+                            tracing::error!("panicked registering `{key}`");
+                            1
+                        }
+                    })
+            })
+            .buffer_unordered(CONCURRENCY)
+            .fold(0, |failed, f| async move { failed + f })
+            .await;
+    }
 
-            // Target key
-            let mut target = source
-                .trim_start_matches("mhs-upload")
-                .trim_start_matches('/')
-                .to_owned();
-
-            // Collection id (uuid)
-            let collection_id = target.split('/').next().unwrap_or_default().to_owned();
-
-            // Get datetime from filename for alerts
-            if collection_id == "35ff8133-364a-47eb-a145-0d641b706bff" {
-                datetime = DateTime::parse_from_str(
-                    &format!("{}+0000", target.split('.').nth(2).unwrap()),
-                    "%Y%m%d%H%M%z",
-                )?
-                .into();
-            }
+    if failed > 0 {
+        tracing::warn!("failed to register {failed} objects");
+    }
+
+    Ok(())
+}
+
+/// Register a single uploaded object
+async fn register(
+    object: &Object,
+    prefix: &str,
+    now: &DateTime<Utc>,
+    radar_cog: bool,
+    cosmo_ensemble: bool,
+    db: &Db,
+    s3: &S3,
+) -> anyhow::Result<()> {
+    // Last modified
+    let mut datetime = object
+        .last_modified
+        .context("missing last modified date")?
+        .to_chrono_utc();
+    let age = *now - datetime;
+
+    // Source key
+    let source = object.key().unwrap_or_default();
+    if source.is_empty()
+            || source.ends_with('/')
+            || source.contains("/.")
+            // || (prefix.is_empty() && source.starts_with("mhs-upload"))
+            || (prefix.is_empty() && source.starts_with("a6296aa9-d183-45c3-90fc-f03ec7d637be"))
+            || (!prefix.is_empty() && age.num_seconds() < 10)
+            || (!prefix.is_empty() && age.num_seconds() >= 70 && age.num_minutes() % 5 != 0)
+    {
+        return Ok(());
+    }
 
-            // Model perculiarities
-            if collection_id == "a6296aa9-d183-45c3-90fc-f03ec7d637be" {
-                // cut initime form target
-                let start = target.find("initime").unwrap();
-                let end = start + 19; // xxx_initime_2022062300_xxx
+    // Target key
+    let mut target = source
+        .trim_start_matches("mhs-upload")
+        .trim_start_matches('/')
+        .to_owned();
+
+    // Collection id (uuid)
+    let collection_id = target.split('/').next().unwrap_or_default().to_owned();
+
+    // Get datetime from filename for alerts
+    if collection_id == "35ff8133-364a-47eb-a145-0d641b706bff" {
+        datetime = DateTime::parse_from_str(
+            &format!(
+                "{}+0000",
+                target.split('.').nth(2).context("missing datetime")?
+            ),
+            "%Y%m%d%H%M%z",
+        )?
+        .into();
+    }
 
-                datetime = DateTime::parse_from_str(
-                    &format!("{}00+0000", &target[start..end].split('_').nth(1).unwrap()),
-                    "%Y%m%d%H%M%z",
-                )?
-                .into();
+    // Model perculiarities
+    if collection_id == "a6296aa9-d183-45c3-90fc-f03ec7d637be" {
+        // cut initime form target
+        let start = target.find("initime").context("missing initime")?;
+        let end = start + 19; // xxx_initime_2022062300_xxx
+        let initime = target
+            .get(start..end)
+            .and_then(|initime| initime.split('_').nth(1))
+            .context("invalid initime")?;
 
-                target = format!("{}{}", &target[..start], &target[end..]);
-            }
+        datetime = DateTime::parse_from_str(&format!("{initime}00+0000"), "%Y%m%d%H%M%z")?.into();
 
-            // Asset id (defaults to file name)
-            let asset_id = Path::new(&target)
-                .file_name()
-                .unwrap_or_default()
-                .to_str()
-                .unwrap();
-
-            // Create asset
-            let mut asset = Asset::new(format!("{AWS_S3_BUCKET_BASE}/{target}"));
-            asset.roles = vec!["data".to_string()];
-            asset.r#type = match target.split('.').last() {
-                Some("json") => Some(JSON.to_string()),
-                Some("csv") => Some("text/csv".to_string()),
-                Some("h5") => Some("application/x-hdf5".to_string()),
-                Some("nc") => Some("application/netcdf".to_string()),
-                Some("tiff") => Some("image/tiff".to_string()),
-                Some("cap") => Some("text/xml".to_string()),
-                Some("zip") => Some("application/zip".to_string()),
-                Some("grib2") => Some("application/wmo-grib2".to_string()),
-                _ => None,
-            };
-
-            // Update collection/item
-            let result = match collection_id.as_str() {
-                "0a62455f-c39c-4084-bd54-36ee2192d3af" | "ad2b1452-9f3c-4137-9822-9758298bc025" => {
-                    if source != target {
-                        copy_object(source, &target, &s3).await.unwrap();
-                    }
+        target = format!("{}{}", &target[..start], &target[end..]);
+    }
 
-                    if source.ends_with("ch.meteoschweiz.messwerte-lufttemperatur-10min_en.json")
-                        || source.ends_with("observations-hourly.csv")
-                    {
-                        load_items_from_object(source, &collection_id, &db, &s3)
-                            .await
-                            .unwrap();
+    // Asset id (defaults to file name)
+    let asset_id = Path::new(&target)
+        .file_name()
+        .unwrap_or_default()
+        .to_str()
+        .context("invalid file name")?;
+
+    // Create asset
+    let mut asset = Asset::new(format!("{AWS_S3_BUCKET_BASE}/{target}"));
+    asset.roles = vec!["data".to_string()];
+    asset.r#type = match media_type::sniff_object(AWS_S3_BUCKET, source, s3).await {
+            Some(detected) => {
+                if let Some(extension) = media_type::from_extension(&target) {
+                    if !media_type::matches(extension, detected) {
+                        tracing::warn!(
+                            "content of `{source}` is `{detected}` but extension suggests `{extension}`"
+                        );
                     }
-                    asset_to_collection(&collection_id, asset_id, asset, &db).await
                 }
-                c => {
-                    // Get item (skip key if no mapping)
-                    let item_id = match c {
-                        "e2e5132c-85df-417a-8706-f75068d4937e"
-                        | "e74c17ea-0822-44db-bef9-f37135a68245"
-                        | "7880287e-5d4b-4e15-b13f-846df89979a3" => "meteoswiss.radar.precip",
-                        "ed6a30c9-672e-4d8f-95e4-8c5bef8ab417" => {
-                            "klimanormwerte.temperatur.1961-1990"
-                        }
-                        "b46a8f8d-bc48-41d3-b20a-de61d0763318" => {
-                            asset_id.split('_').nth(1).unwrap()
-                        }
-                        "4ccc5153-cc27-47b8-abee-9d6e12e19701" => {
-                            &asset_id.split('_').last().unwrap()[..8]
-                        }
-                        "35ff8133-364a-47eb-a145-0d641b706bff" => asset_id.trim_end_matches(".cap"),
-                        "a6296aa9-d183-45c3-90fc-f03ec7d637be" => {
-                            asset_id.trim_end_matches(".grib2")
-                        }
-                        _ => {
-                            tracing::warn!("no mapping for collection `{collection_id}`");
-                            continue;
-                        }
-                    };
+                Some(media_type::refine(detected, &target))
+            }
+            None => media_type::from_extension(&target),
+        }
+        .map(ToString::to_string);
+
+    // Target item (skip key if no mapping), collection assets otherwise
+    let item_id = match collection_id.as_str() {
+        "0a62455f-c39c-4084-bd54-36ee2192d3af" | "ad2b1452-9f3c-4137-9822-9758298bc025" => None,
+        "e2e5132c-85df-417a-8706-f75068d4937e"
+        | "e74c17ea-0822-44db-bef9-f37135a68245"
+        | "7880287e-5d4b-4e15-b13f-846df89979a3" => Some("meteoswiss.radar.precip"),
+        "ed6a30c9-672e-4d8f-95e4-8c5bef8ab417" => Some("klimanormwerte.temperatur.1961-1990"),
+        "b46a8f8d-bc48-41d3-b20a-de61d0763318" => {
+            Some(asset_id.split('_').nth(1).context("invalid asset id")?)
+        }
+        "4ccc5153-cc27-47b8-abee-9d6e12e19701" => Some(
+            asset_id
+                .rsplit('_')
+                .next()
+                .and_then(|date| date.get(..8))
+                .context("invalid asset id")?,
+        ),
+        "35ff8133-364a-47eb-a145-0d641b706bff" => Some(asset_id.trim_end_matches(".cap")),
+        "a6296aa9-d183-45c3-90fc-f03ec7d637be" => Some(grib::stem(asset_id)),
+        _ => {
+            tracing::warn!("no mapping for collection `{collection_id}`");
+            return Ok(());
+        }
+    };
 
-                    if source != target {
-                        copy_object(source, &target, &s3).await.unwrap();
-                    }
+    // Render thumbnails of rasters
+    let raster = matches!(
+        asset.r#type.as_deref(),
+        Some(t) if t == media_type::TIFF || t == media_type::HDF5
+    );
+    let quicklook = item_id
+        .filter(|_| raster && RADAR.contains(&collection_id.as_str()))
+        .map(|item_id| (item_id.to_owned(), asset.href.to_owned()));
+
+    // Convert radar composites to COG
+    let conversion = item_id
+        .filter(|_| radar_cog && RADAR.contains(&collection_id.as_str()))
+        .filter(|_| asset.r#type.as_deref() == Some(media_type::HDF5))
+        .map(|item_id| (item_id.to_owned(), asset.clone()));
+
+    // Ensemble statistics once all members arrived
+    let member = item_id
+        .filter(|_| cosmo_ensemble && collection_id == ensemble::COSMO_1E)
+        .map(ToOwned::to_owned);
+
+    // Update collection/item
+    let mut created = false;
+    let mut archive = None;
+    let result: anyhow::Result<()> = async {
+        // Validate target before copying the object
+        check_target(&collection_id, item_id, db).await?;
+
+        // Preserve the asset about to be replaced
+        if versions::enabled() && source != target {
+            archive = versions::archive(
+                &collection_id,
+                item_id,
+                asset_id,
+                Some(&target),
+                &db.pool,
+                s3,
+            )
+            .await?;
+        }
 
-                    asset_to_item(item_id, &collection_id, asset_id, asset, &datetime, &db).await
+        let acl = visibility::acl(&collection_id, &db.pool).await?;
+        created = copy_to_target(source, &target, acl, s3).await?;
+
+        match item_id {
+            Some(item_id) => {
+                asset_to_item(item_id, &collection_id, asset_id, asset, &datetime, db).await
+            }
+            None => {
+                if source.ends_with("ch.meteoschweiz.messwerte-lufttemperatur-10min_en.json")
+                    || source.ends_with("observations-hourly.csv")
+                {
+                    load_items_from_object(source, &collection_id, db, s3).await?;
                 }
-            };
+                asset_to_collection(&collection_id, asset_id, asset, db).await
+            }
+        }
+    }
+    .await;
+
+    // Cleanup
+    match result {
+        Ok(_) => {
+            if let Some(archive) = archive {
+                if let Err(e) = archive.commit(&db.pool).await {
+                    tracing::error!("failed to record asset version: {e}");
+                }
+            }
 
-            // Cleanup
-            match result {
-                Ok(_) => {
-                    if !prefix.is_empty() {
-                        s3.delete_object(AWS_S3_BUCKET, source).await?;
-                    }
+            if let Some((item_id, source)) = conversion {
+                if let Err(e) = cog::convert(
+                    &collection_id,
+                    &item_id,
+                    &cog::with_tif(asset_id),
+                    &source,
+                    &cog::with_tif(&target),
+                    1,
+                    &db.pool,
+                    s3,
+                )
+                .await
+                {
+                    tracing::warn!("failed to convert `{target}` to COG: {e}");
+                }
+            }
+
+            if let Some(item_id) = member {
+                if let Err(e) = ensemble::complete(&collection_id, &item_id, db, s3).await {
+                    tracing::warn!("failed to compute ensemble of `{item_id}`: {e}");
+                }
+            }
+
+            if let Some((item_id, href)) = quicklook {
+                if let Err(e) = thumbnail::create(
+                    &collection_id,
+                    &item_id,
+                    thumbnail::ASSET_ID,
+                    &href,
+                    1,
+                    &thumbnail::default_ramp(),
+                    thumbnail::SIZE,
+                    &db.pool,
+                    s3,
+                )
+                .await
+                {
+                    tracing::warn!("failed to render thumbnail of `{target}`: {e}");
                 }
-                Err(e) => {
-                    tracing::warn!("failed to load asset: {}", e);
+            }
+
+            if !prefix.is_empty() {
+                s3.delete_object(AWS_S3_BUCKET, source).await?;
+            }
+        }
+        Err(e) => {
+            // Rollback copied and replaced objects
+            if let Some(archive) = archive {
+                archive.rollback(&db.pool, s3).await;
+            }
+            if created {
+                match s3.delete_object(AWS_S3_BUCKET, &target).await {
+                    Ok(_) => tracing::info!("removed object `{target}`"),
+                    Err(e) => tracing::warn!("failed to remove object `{target}`: {e}"),
                 }
             }
+
+            return Err(e);
         }
     }
 
     Ok(())
 }
 
+/// Check that the target collection/item exists (or will be created)
+async fn check_target(collection_id: &str, item_id: Option<&str>, db: &Db) -> anyhow::Result<()> {
+    match item_id {
+        Some(item_id) => {
+            if !AUTO_CREATE.contains(&collection_id)
+                && db
+                    .read_feature(collection_id, item_id, &Crs::default())
+                    .await?
+                    .is_none()
+            {
+                bail!("expected existing feature `{item_id}` in collection `{collection_id}`")
+            }
+        }
+        None => {
+            if db.read_collection(collection_id).await?.is_none() {
+                bail!("missing collection `{collection_id}`")
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn asset_to_item(
     item_id: &str,
     collection_id: &str,
@@ -183,58 +373,68 @@ async fn asset_to_item(
     datetime: &DateTime<Utc>,
     db: &Db,
 ) -> anyhow::Result<()> {
-    let mut item = match db
-        .read_feature(collection_id, item_id, &Crs::default())
+    // Add/update asset and datetime
+    let assets = Map::from_iter([(asset_id.to_string(), serde_json::to_value(asset)?)]);
+    let properties = Map::from_iter([(
+        "datetime".to_string(),
+        json!(datetime.to_rfc3339_opts(SecondsFormat::Secs, true)),
+    )]);
+
+    let mut retries = RETRIES;
+    loop {
+        match catalog::merge_item(
+            collection_id,
+            item_id,
+            assets.to_owned(),
+            Some(properties.to_owned()),
+            None,
+            &db.pool,
+        )
         .await?
-    {
-        Some(feature) => feature,
-        None => {
-            if collection_id == "4ccc5153-cc27-47b8-abee-9d6e12e19701"
-                || collection_id == "35ff8133-364a-47eb-a145-0d641b706bff"
-            {
-                let feature = serde_json::from_value(json!(
-                    {
-                        "id": item_id,
-                        "collection": collection_id,
-                        "geometry": {
-                            "type": "Polygon",
-                            "coordinates": [[
-                                [5.96, 45.82],
-                                [10.49,45.82],
-                                [10.49,47.81],
-                                [5.96,47.81],
-                                [5.96,45.82]
-                            ]]
-                        },
-                        "bbox": [5.96, 45.82, 10.49, 47.81],
-                        "properties": {}
-                    }
-                ))
-                .unwrap();
-
-                db.create_feature(&feature).await?;
-                feature
-            } else {
-                bail!("expected existing feature `{item_id}` in collection `{collection_id}`")
+        {
+            Update::Updated => return Ok(()),
+            // Without `If-Match` only if the item was changed or removed concurrently
+            Update::Modified => {
+                bail!("item `{item_id}` in collection `{collection_id}` changed concurrently")
             }
+            Update::NotFound => {}
         }
-    };
-
-    // Add/update datetime
-    let mut map = serde_json::Map::new();
-    map.insert(
-        "datetime".to_string(),
-        json!(datetime.to_rfc3339_opts(SecondsFormat::Secs, true)),
-    );
-    item.append_properties(map);
-
-    // Add/update asset
-    item.assets.insert(asset_id.to_string(), asset);
 
-    // Write item
-    db.update_feature(&item).await?;
+        if !AUTO_CREATE.contains(&collection_id) {
+            bail!("expected existing feature `{item_id}` in collection `{collection_id}`")
+        }
 
-    Ok(())
+        let feature = serde_json::from_value(json!(
+            {
+                "id": item_id,
+                "collection": collection_id,
+                "geometry": {
+                    "type": "Polygon",
+                    "coordinates": [[
+                        [5.96, 45.82],
+                        [10.49,45.82],
+                        [10.49,47.81],
+                        [5.96,47.81],
+                        [5.96,45.82]
+                    ]]
+                },
+                "bbox": [5.96, 45.82, 10.49, 47.81],
+                "properties": properties,
+                "assets": assets
+            }
+        ))
+        .unwrap();
+
+        match db.create_feature(&feature).await {
+            Ok(_) => return Ok(()),
+            // Item might have been created concurrently, retry merging
+            Err(e) if retries > 0 => {
+                tracing::debug!("failed to create item, retrying: {e}");
+                retries -= 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
 }
 
 async fn asset_to_collection(
@@ -243,17 +443,14 @@ async fn asset_to_collection(
     asset: Asset,
     db: &Db,
 ) -> anyhow::Result<()> {
-    // Get collection
-    let mut collection = db
-        .read_collection(collection_id)
-        .await?
-        .unwrap_or_else(|| panic!("missing collection `{collection_id}`"));
-
     // Add/update asset
-    collection.assets.insert(asset_id.to_string(), asset);
+    let assets = Map::from_iter([(asset_id.to_string(), serde_json::to_value(asset)?)]);
+
+    if !catalog::merge_collection(collection_id, assets, &db.pool).await? {
+        bail!("missing collection `{collection_id}`")
+    }
 
-    // Write ollection
-    db.update_collection(&collection).await
+    Ok(())
 }
 
 async fn load_items_from_object(
@@ -266,10 +463,15 @@ async fn load_items_from_object(
     let resp = s3.get_object(AWS_S3_BUCKET, key).await?;
     let data = resp.body.collect().await?.into_bytes();
 
-    let mut features = if key.ends_with("json") {
-        let value = serde_json::from_slice(&data)?;
+    let geojson = key.ends_with("json");
+    let (mut features, crs) = if geojson {
+        let value: Value = serde_json::from_slice(&data)?;
+        let crs = value
+            .pointer("/crs/properties/name")
+            .and_then(Value::as_str)
+            .map(ToOwned::to_owned);
         let fc = geojson::FeatureCollection::from_json_value(value)?;
-        fc.features
+        (fc.features, crs)
     } else {
         let mut rdr = csv::ReaderBuilder::new()
             .delimiter(b';')
@@ -282,25 +484,39 @@ async fn load_items_from_object(
             let record: crate::observation::Observation = result?;
             features.push(record.to_feature());
         }
-        features
+        (features, None)
     };
 
     // Load features
     let now = std::time::Instant::now();
     let count = features.len();
 
-    let proj = crate::proj::Proj::new("EPSG:2056", "EPSG:4326");
-
-    sqlx::query(&format!(r#"TRUNCATE TABLE items."{}""#, collection_id))
-        .execute(&db.pool)
-        .await?;
+    let crs = source_crs(geojson, crs.as_deref(), collection_id, db).await?;
+    let proj = crs.to_wgs84()?;
+
+    // Validate and transform geometries before replacing the items
+    let mut geometries = Vec::with_capacity(count);
+    for (i, feature) in features.iter().enumerate() {
+        let mut geom: geo::Geometry = feature
+            .geometry
+            .to_owned()
+            .ok_or_else(|| anyhow!("missing geometry of feature {i}"))?
+            .try_into()?;
+        if !crs.contains(&geom) {
+            bail!("feature {i} is outside the area of use of `{}`", crs.code())
+        }
+        if let Some(proj) = &proj {
+            geom.transform(&proj.0)?;
+        }
+        geometries.push(geom);
+    }
 
     let mut ids_list = Vec::new();
     let mut properties_list = Vec::new();
     let mut assets_list = Vec::new();
     let mut geom_list = Vec::new();
 
-    for (i, feature) in features.iter_mut().enumerate() {
+    for ((i, feature), geom) in features.iter_mut().enumerate().zip(&geometries) {
         // id
         let id = if let Some(id) = &feature.id {
             match id {
@@ -334,20 +550,29 @@ async fn load_items_from_object(
         assets_list.push(sqlx::types::Json(json!({ id: asset })));
 
         // geom
-        let mut geom: geo::Geometry = feature.geometry.to_owned().unwrap().try_into()?;
-        geom.transform(&proj.0)?;
-        geom_list.push(wkb::geom_to_wkb(&geom).unwrap());
+        geom_list
+            .push(wkb::geom_to_wkb(geom).map_err(|e| anyhow!("failed to encode geometry: {e:?}"))?);
     }
 
+    // Replace the items in one transaction, TRUNCATE locks the table until the
+    // commit, so that concurrent loads of the collection do not interleave
+    let mut tx = db.pool.begin().await?;
+    sqlx::query(&format!(
+        "TRUNCATE TABLE {}",
+        catalog::items_table(collection_id)
+    ))
+    .execute(&mut tx)
+    .await?;
     bulk_load_items(
         collection_id,
         &ids_list,
         &properties_list,
         &geom_list,
         &assets_list,
-        &db.pool,
+        &mut tx,
     )
     .await?;
+    tx.commit().await?;
 
     // stats
     let elapsed = now.elapsed().as_millis() as f64 / 1000.0;
@@ -359,13 +584,62 @@ async fn load_items_from_object(
     Ok(())
 }
 
+/// Source CRS of features loaded into a collection, from the GeoJSON `crs` member,
+/// the `SOURCE_CRS` mapping of collection ids to CRS, e.g.
+/// `{"<collection id>": "EPSG:21781"}`, or the `storageCrs` of the collection,
+/// LV95 otherwise
+async fn source_crs(
+    geojson: bool,
+    crs: Option<&str>,
+    collection_id: &str,
+    db: &Db,
+) -> anyhow::Result<SourceCrs> {
+    let storage_crs: Option<String> = sqlx::query_scalar(
+        "SELECT collection ->> 'storageCrs' FROM meta.collections WHERE id = $1",
+    )
+    .bind(collection_id)
+    .fetch_optional(&db.pool)
+    .await?
+    .flatten();
+
+    let mapping: Option<String> = std::env::var("SOURCE_CRS")
+        .ok()
+        .and_then(|mapping| {
+            serde_json::from_str::<HashMap<String, String>>(&mapping)
+                .map_err(|e| tracing::warn!("invalid `SOURCE_CRS`: {e}"))
+                .ok()
+        })
+        .and_then(|mut mapping| mapping.remove(collection_id));
+
+    resolve_crs(geojson, crs, mapping, storage_crs)
+}
+
+/// CRS of GeoJSON features in order of precedence, the `e`/`n` columns of
+/// observation CSV files are always LV95
+fn resolve_crs(
+    geojson: bool,
+    crs: Option<&str>,
+    mapping: Option<String>,
+    storage_crs: Option<String>,
+) -> anyhow::Result<SourceCrs> {
+    if !geojson {
+        return Ok(SourceCrs::Lv95);
+    }
+    match crs.map(ToOwned::to_owned).or(mapping).or(storage_crs) {
+        Some(name) => SourceCrs::parse(&name).ok_or_else(|| {
+            anyhow!("unsupported CRS `{name}`, expected EPSG:4326, EPSG:21781 or EPSG:2056")
+        }),
+        None => Ok(SourceCrs::Lv95),
+    }
+}
+
 async fn bulk_load_items(
     collection: &str,
     ids: &[String],
     properties: &[Option<sqlx::types::Json<serde_json::Map<String, serde_json::Value>>>],
     geoms: &[Vec<u8>],
     assets: &[sqlx::types::Json<serde_json::Value>],
-    pool: &sqlx::PgPool,
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
 ) -> Result<(), sqlx::Error> {
     let batch_size = 10000;
     let total = geoms.len();
@@ -392,16 +666,16 @@ async fn bulk_load_items(
         }
         sqlx::query(&format!(
             r#"
-            INSERT INTO items."{}" (id, properties, geom, assets)
+            INSERT INTO {} (id, properties, geom, assets)
             SELECT * FROM UNNEST($1::text[], $2::jsonb[], $3::bytea[], $4::jsonb[])
             "#,
-            collection
+            catalog::items_table(collection)
         ))
         .bind(ids_batch)
         .bind(properties_batch)
         .bind(geoms_batch)
         .bind(assets_batch)
-        .execute(pool)
+        .execute(&mut *tx)
         .await?;
 
         start = end;
@@ -411,14 +685,77 @@ async fn bulk_load_items(
     Ok(())
 }
 
-async fn copy_object(source: &str, target: &str, s3: &S3) -> anyhow::Result<()> {
+async fn copy_object(
+    source: &str,
+    target: &str,
+    acl: ObjectCannedAcl,
+    s3: &S3,
+) -> anyhow::Result<()> {
     s3.client
         .copy_object()
         .copy_source(format!("{AWS_S3_BUCKET}/{source}"))
         .bucket(AWS_S3_BUCKET)
         .key(target)
-        .acl(ObjectCannedAcl::PublicRead)
+        .acl(acl)
         .send()
         .await?;
     Ok(())
 }
+
+/// Copy object to target if required, returns whether a new object was created
+async fn copy_to_target(
+    source: &str,
+    target: &str,
+    acl: ObjectCannedAcl,
+    s3: &S3,
+) -> anyhow::Result<bool> {
+    if source == target {
+        return Ok(false);
+    }
+
+    let exists = s3
+        .client
+        .head_object()
+        .bucket(AWS_S3_BUCKET)
+        .key(target)
+        .send()
+        .await
+        .is_ok();
+
+    copy_object(source, target, acl, s3).await?;
+
+    Ok(!exists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crs_of_files() {
+        let lv03 = || Some("EPSG:21781".to_string());
+
+        // GeoJSON `crs` member, `SOURCE_CRS` mapping, storage CRS
+        let crs = resolve_crs(true, Some("EPSG:4326"), lv03(), lv03()).unwrap();
+        assert_eq!(crs, SourceCrs::Wgs84);
+        assert_eq!(
+            resolve_crs(true, None, lv03(), None).unwrap(),
+            SourceCrs::Lv03
+        );
+        assert_eq!(
+            resolve_crs(true, None, None, lv03()).unwrap(),
+            SourceCrs::Lv03
+        );
+        assert_eq!(
+            resolve_crs(true, None, None, None).unwrap(),
+            SourceCrs::Lv95
+        );
+        assert!(resolve_crs(true, Some("EPSG:3857"), None, None).is_err());
+
+        // Observation CSV files are always LV95
+        assert_eq!(
+            resolve_crs(false, None, lv03(), lv03()).unwrap(),
+            SourceCrs::Lv95
+        );
+    }
+}
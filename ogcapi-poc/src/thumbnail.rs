@@ -0,0 +1,291 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use axum::{
+    async_trait,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use image::{imageops::FilterType, ImageOutputFormat, RgbaImage};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use sqlx::PgPool;
+use url::Url;
+
+use ogcapi_drivers::s3::{ByteStream, S3};
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::{
+    processes::{Execute, Process},
+    stac::Asset,
+};
+
+use crate::{
+    catalog::{self, Update},
+    loader,
+    raster::{self, Raster},
+    visibility, AWS_S3_BUCKET, AWS_S3_BUCKET_BASE,
+};
+
+/// Default thumbnail asset id
+pub(crate) const ASSET_ID: &str = "thumbnail";
+
+/// Default maximum width/height of thumbnails in pixels
+pub(crate) const SIZE: u32 = 256;
+
+/// Quicklook thumbnail generator
+pub(crate) struct ThumbnailCreator;
+
+/// Thumbnail input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct ThumbnailCreatorInputs {
+    /// Collection `id`
+    collection: String,
+    /// Item `id`
+    item: String,
+    /// Asset `id` of the raster to render
+    asset: String,
+    /// Asset `id` of the thumbnail
+    id: Option<String>,
+    /// Raster band, starting at 1
+    band: Option<isize>,
+    /// Maximum width/height in pixels
+    size: Option<u32>,
+    /// Colour ramp, defaults to a precipitation ramp (mm/h)
+    #[serde(rename = "colorRamp")]
+    color_ramp: Option<Vec<ColorStop>>,
+}
+
+/// Thumbnail output schema
+#[derive(Serialize, JsonSchema)]
+struct ThumbnailCreatorOutputs {
+    /// Href of the thumbnail
+    href: String,
+}
+
+/// Colour of a value, values in between are interpolated linearly
+#[derive(Deserialize, Debug, Clone, JsonSchema)]
+pub(crate) struct ColorStop {
+    value: f64,
+    /// Hex colour `#rrggbb` or `#rrggbbaa`
+    color: String,
+}
+
+/// Precipitation ramp, values below the first stop are transparent
+pub(crate) fn default_ramp() -> Vec<ColorStop> {
+    [
+        (0.1, "#c8e6ff"),
+        (1.0, "#6496ff"),
+        (2.0, "#0032ff"),
+        (5.0, "#00c800"),
+        (10.0, "#ffff00"),
+        (20.0, "#ff9600"),
+        (50.0, "#ff0000"),
+        (100.0, "#c800c8"),
+    ]
+    .into_iter()
+    .map(|(value, color)| ColorStop {
+        value,
+        color: color.to_string(),
+    })
+    .collect()
+}
+
+#[async_trait]
+impl Processor for ThumbnailCreator {
+    fn id(&self) -> String {
+        "create-thumbnail".to_string()
+    }
+    fn process(&self) -> Process {
+        // Config schema generation
+        let settings = SchemaSettings::default().with(|s| {
+            s.option_nullable = false;
+            s.option_add_null_type = false;
+            s.inline_subschemas = true;
+        });
+        let gen = settings.into_generator();
+
+        Process::new(
+            self.id(),
+            "0.1.0",
+            &serde_json::to_value(
+                &gen.clone()
+                    .into_root_schema_for::<ThumbnailCreatorInputs>()
+                    .schema,
+            )
+            .unwrap(),
+            &serde_json::to_value(&gen.into_root_schema_for::<ThumbnailCreatorOutputs>().schema)
+                .unwrap(),
+        )
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: ThumbnailCreatorInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let source = catalog::asset(
+            &inputs.collection,
+            Some(&inputs.item),
+            &inputs.asset,
+            &state.db.pool,
+        )
+        .await?
+        .and_then(|asset| serde_json::from_value::<Asset>(asset).ok())
+        .ok_or_else(|| {
+            Error::Exception(
+                StatusCode::NOT_FOUND,
+                format!("Asset `{}` not found", inputs.asset),
+            )
+        })?;
+
+        let ramp = inputs.color_ramp.unwrap_or_else(default_ramp);
+        parse_ramp(&ramp).map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let asset = create(
+            &inputs.collection,
+            &inputs.item,
+            inputs.id.as_deref().unwrap_or(ASSET_ID),
+            &source.href,
+            inputs.band.unwrap_or(1),
+            &ramp,
+            inputs.size.unwrap_or(SIZE),
+            &state.db.pool,
+            &state.s3,
+        )
+        .await?;
+
+        Ok(Json(ThumbnailCreatorOutputs { href: asset.href }).into_response())
+    }
+}
+
+/// Render a thumbnail of the raster at `href` and attach it to the item
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn create(
+    collection: &str,
+    item: &str,
+    id: &str,
+    href: &str,
+    band: isize,
+    ramp: &[ColorStop],
+    size: u32,
+    pool: &PgPool,
+    s3: &S3,
+) -> anyhow::Result<Asset> {
+    let bytes = raster::fetch(href, s3).await?;
+    let raster = Raster::from_bytes(bytes, band).await?;
+
+    let stops = parse_ramp(ramp)?;
+    let png = tokio::task::spawn_blocking(move || render(&raster, &stops, size)).await??;
+
+    // Upload, one object per thumbnail asset of the item
+    let key = format!("{collection}/thumbnails/{item}/{id}.png");
+    let created = !loader::object_exists(&key, s3).await;
+    s3.client
+        .put_object()
+        .bucket(AWS_S3_BUCKET)
+        .key(&key)
+        .body(ByteStream::from(png))
+        .content_type("image/png")
+        .acl(visibility::acl(collection, pool).await?)
+        .send()
+        .await
+        .context("Failed to upload thumbnail")?;
+
+    let mut asset = Asset::new(format!("{AWS_S3_BUCKET_BASE}/{key}"));
+    asset.title = Some("Thumbnail".to_string());
+    asset.r#type = Some("image/png".to_string());
+    asset.roles = vec!["thumbnail".to_string(), "overview".to_string()];
+
+    // Attach to item
+    let assets = Map::from_iter([(id.to_owned(), serde_json::to_value(&asset).unwrap())]);
+    let e = match catalog::merge_item(collection, item, assets, None, None, pool).await {
+        Ok(Update::Updated) => return Ok(asset),
+        Ok(_) => anyhow::anyhow!("Item `{item}` not found"),
+        Err(e) => e,
+    };
+    if created {
+        anyhow::bail!("{e}, {}", loader::remove_upload(&key, s3).await);
+    }
+    Err(e)
+}
+
+/// Parse the colour ramp into sorted RGBA stops
+fn parse_ramp(ramp: &[ColorStop]) -> anyhow::Result<Vec<(f64, [u8; 4])>> {
+    let mut stops = ramp
+        .iter()
+        .map(|stop| {
+            let hex = stop.color.trim_start_matches('#');
+            let channel = |i: usize| {
+                hex.get(i..i + 2)
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+                    .with_context(|| format!("Invalid colour `{}`", stop.color))
+            };
+            if hex.len() != 6 && hex.len() != 8 {
+                anyhow::bail!("Invalid colour `{}`", stop.color);
+            }
+            let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+            Ok((stop.value, [channel(0)?, channel(2)?, channel(4)?, alpha]))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if stops.is_empty() {
+        anyhow::bail!("Colour ramp is empty");
+    }
+    stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    Ok(stops)
+}
+
+/// Colour of a value, transparent for no data and values below the ramp
+fn color(value: f64, stops: &[(f64, [u8; 4])]) -> [u8; 4] {
+    match stops.iter().position(|(v, _)| value < *v) {
+        Some(0) => [0, 0, 0, 0],
+        Some(i) => {
+            let (v0, c0) = stops[i - 1];
+            let (v1, c1) = stops[i];
+            let t = (value - v0) / (v1 - v0);
+            [0, 1, 2, 3].map(|k| (c0[k] as f64 + t * (c1[k] as f64 - c0[k] as f64)).round() as u8)
+        }
+        None => stops.last().unwrap().1,
+    }
+}
+
+/// Render a raster to a PNG of at most `size` pixels width/height
+fn render(raster: &Raster, stops: &[(f64, [u8; 4])], size: u32) -> anyhow::Result<Vec<u8>> {
+    // Rows of south-up rasters are flipped
+    let south_up = raster.geo_transform[5] > 0.0;
+
+    let mut pixels = Vec::with_capacity(raster.width * raster.height * 4);
+    for row in 0..raster.height {
+        let row = if south_up {
+            raster.height - 1 - row
+        } else {
+            row
+        };
+        for value in &raster.data[row * raster.width..(row + 1) * raster.width] {
+            if raster.is_valid(*value) {
+                pixels.extend(color(*value, stops));
+            } else {
+                pixels.extend([0, 0, 0, 0]);
+            }
+        }
+    }
+
+    let image = RgbaImage::from_raw(raster.width as u32, raster.height as u32, pixels)
+        .context("Invalid raster size")?;
+
+    let scale = (size as f64 / raster.width.max(raster.height) as f64).min(1.0);
+    let image = image::imageops::resize(
+        &image,
+        ((raster.width as f64 * scale).round() as u32).max(1),
+        ((raster.height as f64 * scale).round() as u32).max(1),
+        FilterType::Nearest,
+    );
+
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+
+    Ok(png)
+}
@@ -0,0 +1,361 @@
+use axum::{
+    async_trait,
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use futures::{stream, StreamExt};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::{
+    processes::{Execute, Process},
+    stac::Asset,
+};
+
+use crate::{
+    catalog, media_type,
+    raster::{self, Band, Raster, TargetCrs},
+};
+
+/// Maximum number of assets read per request
+const MAX_ASSETS: usize = 500;
+
+/// Number of assets read concurrently
+const CONCURRENCY: usize = 4;
+
+/// Point value and time series extractor
+pub(crate) struct TimeSeriesExtractor;
+
+/// Time series input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct TimeSeriesInputs {
+    /// Collection `id`
+    collection: String,
+    /// Point coordinates `[x, y]`, e.g. `[lon, lat]`
+    point: [f64; 2],
+    /// CRS of the point
+    #[serde(default)]
+    crs: TargetCrs,
+    /// Datetime interval `start/end`, open ends as `..`
+    datetime: Option<String>,
+    /// Only read items with these properties, e.g. `{"member": "000"}`
+    #[serde(default)]
+    properties: Map<String, Value>,
+    /// GRIB parameter (element) to read, e.g. `T`, the first band if not set
+    parameter: Option<String>,
+    /// Output format
+    #[serde(default)]
+    format: Format,
+}
+
+/// Output format
+#[derive(Deserialize, Debug, Default, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Json,
+    Csv,
+    CoverageJson,
+}
+
+/// Time series output schema
+#[derive(Serialize, JsonSchema)]
+struct TimeSeriesOutputs {
+    /// Values ordered by time
+    values: Vec<TimeValue>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TimeValue {
+    /// Valid time of the value
+    datetime: String,
+    /// Value at the point, `null` for no data or if the asset could not be read
+    value: Option<f64>,
+    /// Item `id`
+    item: String,
+    /// Asset `id`
+    asset: String,
+    /// Error message if the asset could not be read
+    message: Option<String>,
+}
+
+#[async_trait]
+impl Processor for TimeSeriesExtractor {
+    fn id(&self) -> String {
+        "extract-timeseries".to_string()
+    }
+    fn process(&self) -> Process {
+        // Config schema generation
+        let settings = SchemaSettings::default().with(|s| {
+            s.option_nullable = false;
+            s.option_add_null_type = false;
+            s.inline_subschemas = true;
+        });
+        let gen = settings.into_generator();
+
+        Process::new(
+            self.id(),
+            "0.1.0",
+            &serde_json::to_value(
+                &gen.clone()
+                    .into_root_schema_for::<TimeSeriesInputs>()
+                    .schema,
+            )
+            .unwrap(),
+            &serde_json::to_value(&gen.into_root_schema_for::<TimeSeriesOutputs>().schema).unwrap(),
+        )
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: TimeSeriesInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let (start, end) = interval(inputs.datetime.as_deref())
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e))?;
+
+        // Raster assets within the interval
+        let assets = raster_assets(&inputs, state)
+            .await?
+            .into_iter()
+            .filter(|(datetime, ..)| start.map_or(true, |s| *datetime >= s))
+            .filter(|(datetime, ..)| end.map_or(true, |e| *datetime <= e))
+            .collect::<Vec<_>>();
+
+        if assets.len() > MAX_ASSETS {
+            return Err(Error::Exception(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Request matches {} assets, narrow `datetime` or `properties` to at most {MAX_ASSETS}",
+                    assets.len()
+                ),
+            ));
+        }
+
+        // Read values
+        let crs = inputs.crs.code();
+        let point = (inputs.point[0], inputs.point[1]);
+        let parameter = inputs.parameter.to_owned();
+        let mut values: Vec<TimeValue> = stream::iter(assets)
+            .map(|(datetime, item, id, asset)| {
+                let parameter = parameter.to_owned();
+                async move {
+                    let band = match parameter {
+                        Some(parameter) => Band::Element(parameter),
+                        None => Band::Index(1),
+                    };
+                    let value = match raster::fetch(&asset.href, &state.s3).await {
+                        Ok(bytes) => match Raster::from_bytes(bytes, band).await {
+                            Ok(raster) => raster.sample(crs.to_string(), point).await,
+                            Err(e) => Err(e),
+                        },
+                        Err(e) => Err(e),
+                    };
+                    let (value, message) = match value {
+                        Ok(value) => (value, None),
+                        Err(e) => {
+                            tracing::warn!("failed to read `{}`: {e}", asset.href);
+                            (None, Some(e.to_string()))
+                        }
+                    };
+
+                    TimeValue {
+                        datetime: datetime.to_rfc3339(),
+                        value,
+                        item,
+                        asset: id,
+                        message,
+                    }
+                }
+            })
+            .buffered(CONCURRENCY)
+            .collect()
+            .await;
+
+        values.sort_by(|a, b| a.datetime.cmp(&b.datetime));
+
+        match inputs.format {
+            Format::Json => Ok(Json(TimeSeriesOutputs { values }).into_response()),
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                for value in &values {
+                    writer.serialize(value).map_err(anyhow::Error::from)?;
+                }
+                let csv = writer.into_inner().map_err(|e| anyhow::anyhow!("{e}"))?;
+
+                Ok(([(CONTENT_TYPE, media_type::CSV)], csv).into_response())
+            }
+            Format::CoverageJson => Ok((
+                [(CONTENT_TYPE, "application/prs.coverage+json")],
+                Json(coverage(&inputs, &values)),
+            )
+                .into_response()),
+        }
+    }
+}
+
+/// Parse a datetime interval `start/end` or a single datetime
+fn interval(
+    datetime: Option<&str>,
+) -> std::result::Result<(Option<DateTime<Utc>>, Option<DateTime<Utc>>), String> {
+    let parse = |s: &str| -> std::result::Result<Option<DateTime<Utc>>, String> {
+        match s.trim() {
+            "" | ".." => Ok(None),
+            s => DateTime::parse_from_rfc3339(s)
+                .map(|d| Some(d.into()))
+                .map_err(|e| format!("Invalid datetime `{s}`: {e}")),
+        }
+    };
+
+    match datetime {
+        None => Ok((None, None)),
+        Some(datetime) => match datetime.split_once('/') {
+            Some((start, end)) => Ok((parse(start)?, parse(end)?)),
+            None => {
+                let instant = parse(datetime)?;
+                Ok((instant, instant))
+            }
+        },
+    }
+}
+
+/// Raster assets of the matching items with their valid time
+async fn raster_assets(
+    inputs: &TimeSeriesInputs,
+    state: &State,
+) -> Result<Vec<(DateTime<Utc>, String, String, Asset)>> {
+    let items = catalog::items(
+        &inputs.collection,
+        inputs.properties.to_owned(),
+        &state.db.pool,
+    )
+    .await?;
+
+    let mut assets = Vec::new();
+    for item in items {
+        let properties = item.properties.map(|p| p.0).unwrap_or_default();
+
+        for (id, asset) in item.assets.map(|a| a.0).unwrap_or_default() {
+            let asset: Asset = match serde_json::from_value(asset) {
+                Ok(asset) => asset,
+                Err(_) => continue,
+            };
+            if !is_raster(&id, &asset) {
+                continue;
+            }
+            if let Some(datetime) = valid_time(&id, &properties) {
+                assets.push((datetime, item.id.to_owned(), id, asset));
+            }
+        }
+    }
+
+    Ok(assets)
+}
+
+/// Whether the asset is a GeoTIFF, HDF5, NetCDF or GRIB2 raster
+pub(crate) fn is_raster(id: &str, asset: &Asset) -> bool {
+    let media_type = asset
+        .r#type
+        .as_deref()
+        .or_else(|| media_type::from_extension(id))
+        .unwrap_or_default();
+
+    [
+        media_type::TIFF,
+        media_type::HDF5,
+        media_type::NETCDF,
+        media_type::GRIB2,
+    ]
+    .iter()
+    .any(|t| media_type.starts_with(t))
+}
+
+/// Valid time of an asset
+///
+/// Radar composites encode it in the file name (`RZCyyDDDHHMM...`), model
+/// fields as `leadtime` hours after the item `datetime`.
+fn valid_time(id: &str, properties: &Map<String, Value>) -> Option<DateTime<Utc>> {
+    // Radar composite, e.g. `RZC220460300VL.801.h5`
+    if let Some(datetime) = id.get(3..12).and_then(|digits| {
+        let year = 2000 + digits.get(0..2)?.parse::<i32>().ok()?;
+        let day = digits.get(2..5)?.parse::<u32>().ok()?;
+        let hour = digits.get(5..7)?.parse::<u32>().ok()?;
+        let minute = digits.get(7..9)?.parse::<u32>().ok()?;
+        NaiveDate::from_yo_opt(year, day)?.and_hms_opt(hour, minute, 0)
+    }) {
+        if id[..3].chars().all(|c| c.is_ascii_uppercase()) {
+            return Some(DateTime::from_utc(datetime, Utc));
+        }
+    }
+
+    let datetime: DateTime<Utc> = properties
+        .get("datetime")
+        .and_then(Value::as_str)
+        .and_then(|d| DateTime::parse_from_rfc3339(d).ok())?
+        .into();
+
+    // Forecast lead time in hours
+    match properties
+        .get("leadtime")
+        .and_then(Value::as_str)
+        .and_then(|l| l.parse::<i64>().ok())
+    {
+        Some(leadtime) => Some(datetime + Duration::hours(leadtime)),
+        None => Some(datetime),
+    }
+}
+
+/// CoverageJSON point series
+fn coverage(inputs: &TimeSeriesInputs, values: &[TimeValue]) -> Value {
+    let system = match inputs.crs {
+        TargetCrs::Wgs84 => json!({
+            "type": "GeographicCRS",
+            "id": "http://www.opengis.net/def/crs/OGC/1.3/CRS84"
+        }),
+        TargetCrs::Lv95 => json!({
+            "type": "ProjectedCRS",
+            "id": "http://www.opengis.net/def/crs/EPSG/0/2056"
+        }),
+    };
+    let parameter = inputs.parameter.as_deref().unwrap_or("value");
+
+    json!({
+        "type": "Coverage",
+        "domain": {
+            "type": "Domain",
+            "domainType": "PointSeries",
+            "axes": {
+                "x": { "values": [inputs.point[0]] },
+                "y": { "values": [inputs.point[1]] },
+                "t": { "values": values.iter().map(|v| &v.datetime).collect::<Vec<_>>() }
+            },
+            "referencing": [
+                { "coordinates": ["x", "y"], "system": system },
+                {
+                    "coordinates": ["t"],
+                    "system": { "type": "TemporalRS", "calendar": "Gregorian" }
+                }
+            ]
+        },
+        "parameters": {
+            parameter: {
+                "type": "Parameter",
+                "observedProperty": { "label": { "en": parameter } }
+            }
+        },
+        "ranges": {
+            parameter: {
+                "type": "NdArray",
+                "dataType": "float",
+                "axisNames": ["t"],
+                "shape": [values.len()],
+                "values": values.iter().map(|v| v.value).collect::<Vec<_>>()
+            }
+        }
+    })
+}
@@ -0,0 +1,222 @@
+use std::{collections::HashMap, sync::RwLock, time::SystemTime};
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::keys::Scope;
+
+/// Users loaded from the file at `APP_USERS`, reloaded when the file changes
+static USERS: Lazy<RwLock<Users>> = Lazy::new(|| RwLock::new(Users::default()));
+
+/// Hash verified for unknown users to not reveal them by the response time
+static DUMMY: Lazy<String> =
+    Lazy::new(|| bcrypt::hash("", bcrypt::DEFAULT_COST).unwrap_or_default());
+
+/// User entry of the users file
+#[derive(Deserialize, Debug)]
+struct UserEntry {
+    name: String,
+    /// Argon2 (`$argon2id$...`) or bcrypt (`$2b$...`) password hash
+    password: String,
+    /// Granted scopes
+    #[serde(default)]
+    scopes: Vec<Scope>,
+    #[serde(default = "enabled")]
+    enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Default)]
+struct Users {
+    /// Modification time of the loaded file
+    modified: Option<SystemTime>,
+    users: HashMap<String, UserEntry>,
+}
+
+/// Path of the users file, if configured
+pub(crate) fn path() -> Option<String> {
+    std::env::var("APP_USERS").ok().filter(|p| !p.is_empty())
+}
+
+/// Reload the users file if it changed since it was last loaded
+fn reload(users: &RwLock<Users>, path: &str) -> anyhow::Result<()> {
+    let modified = std::fs::metadata(path)?.modified()?;
+
+    if users.read().unwrap().modified == Some(modified) {
+        return Ok(());
+    }
+
+    let entries: Vec<UserEntry> = serde_json::from_slice(&std::fs::read(path)?)?;
+    *users.write().unwrap() = Users {
+        modified: Some(modified),
+        users: entries
+            .into_iter()
+            .map(|user| (user.name.to_owned(), user))
+            .collect(),
+    };
+
+    tracing::info!("loaded users from `{path}`");
+
+    Ok(())
+}
+
+/// Verify the credentials against the users file, returns the scopes of the
+/// user if they are valid
+pub(crate) async fn verify(
+    path: String,
+    name: String,
+    password: String,
+) -> anyhow::Result<Option<Vec<Scope>>> {
+    // Password hashing and file access block
+    tokio::task::spawn_blocking(move || check(&USERS, &path, &name, &password)).await?
+}
+
+fn check(
+    users: &RwLock<Users>,
+    path: &str,
+    name: &str,
+    password: &str,
+) -> anyhow::Result<Option<Vec<Scope>>> {
+    // Keep serving the last loaded users if the file is temporarily invalid
+    if let Err(e) = reload(users, path) {
+        tracing::warn!("failed to load users from `{path}`: {e}");
+    }
+
+    let users = users.read().unwrap();
+    let user = match users.users.get(name) {
+        Some(user) if user.enabled => user,
+        _ => {
+            let _ = bcrypt::verify(password, &DUMMY);
+            return Ok(None);
+        }
+    };
+
+    let valid = if user.password.starts_with("$2") {
+        bcrypt::verify(password, &user.password)?
+    } else {
+        let hash = PasswordHash::new(&user.password).map_err(|e| anyhow::anyhow!("{e}"))?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    };
+
+    Ok(valid.then(|| user.scopes.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// Write a users file, returns its path
+    fn users_file(users: serde_json::Value) -> String {
+        let path = std::env::temp_dir().join(format!("{}.json", Uuid::new_v4()));
+        std::fs::write(&path, users.to_string()).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn argon2_hash(password: &str) -> String {
+        let salt = SaltString::new("c29tZXNhbHRzb21lc2FsdA").unwrap();
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn argon2_password() {
+        let path = users_file(json!([{
+            "name": "alice",
+            "password": argon2_hash("secret"),
+            "scopes": ["assets:write"]
+        }]));
+        let users = RwLock::new(Users::default());
+
+        assert_eq!(
+            check(&users, &path, "alice", "secret").unwrap(),
+            Some(vec![Scope::AssetsWrite])
+        );
+        assert_eq!(check(&users, &path, "alice", "wrong").unwrap(), None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bcrypt_password() {
+        let path = users_file(json!([{
+            "name": "bob",
+            "password": bcrypt::hash("secret", 4).unwrap(),
+        }]));
+        let users = RwLock::new(Users::default());
+
+        assert_eq!(check(&users, &path, "bob", "secret").unwrap(), Some(vec![]));
+        assert_eq!(check(&users, &path, "bob", "wrong").unwrap(), None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn disabled_user() {
+        let path = users_file(json!([{
+            "name": "carol",
+            "password": bcrypt::hash("secret", 4).unwrap(),
+            "enabled": false
+        }]));
+        let users = RwLock::new(Users::default());
+
+        assert_eq!(check(&users, &path, "carol", "secret").unwrap(), None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unknown_user() {
+        let path = users_file(json!([]));
+        let users = RwLock::new(Users::default());
+
+        assert_eq!(check(&users, &path, "dave", "secret").unwrap(), None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reload_on_change() {
+        let path = users_file(json!([{
+            "name": "erin",
+            "password": bcrypt::hash("secret", 4).unwrap(),
+        }]));
+        let users = RwLock::new(Users::default());
+
+        assert!(check(&users, &path, "erin", "secret").unwrap().is_some());
+
+        std::fs::write(
+            &path,
+            json!([{
+                "name": "erin",
+                "password": bcrypt::hash("changed", 4).unwrap(),
+            }])
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+
+        assert_eq!(check(&users, &path, "erin", "secret").unwrap(), None);
+        assert!(check(&users, &path, "erin", "changed").unwrap().is_some());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::{types::Json as Jsonb, PgPool};
+
+use ogcapi_drivers::s3::S3;
+use ogcapi_services::{Error, Result};
+
+use crate::{assets::s3_key, catalog, visibility, AWS_S3_BUCKET, AWS_S3_BUCKET_BASE};
+
+/// Key prefix of archived S3 objects
+const PREFIX: &str = "versions";
+
+/// Whether replaced assets are versioned by default (`ASSET_VERSIONING`)
+pub(crate) fn enabled() -> bool {
+    std::env::var("ASSET_VERSIONING").unwrap_or_else(|_| "false".to_string()) == "true"
+}
+
+/// Create the asset history table
+pub(crate) async fn setup(pool: &PgPool) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS meta.asset_versions (
+            collection text NOT NULL,
+            item text NOT NULL DEFAULT '',
+            asset text NOT NULL,
+            version text NOT NULL,
+            value jsonb NOT NULL,
+            archived timestamptz NOT NULL DEFAULT now(),
+            PRIMARY KEY (collection, item, asset, version)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Current asset preserved before it is replaced, only recorded in the history
+/// once the replacement succeeded
+pub(crate) struct Archive {
+    collection: String,
+    item: Option<String>,
+    id: String,
+    version: String,
+    asset: Map<String, Value>,
+    /// Overwritten S3 object and its preserved copy
+    preserved: Option<(String, String)>,
+}
+
+/// Preserve the current asset before it is replaced. Call [`Archive::commit`]
+/// after the replacement succeeded and [`Archive::rollback`] otherwise.
+///
+/// The S3 object is preserved under a versioned key if it is about to be
+/// overwritten by the object with key `overwrites`.
+pub(crate) async fn archive(
+    collection: &str,
+    item: Option<&str>,
+    id: &str,
+    overwrites: Option<&str>,
+    pool: &PgPool,
+    s3: &S3,
+) -> anyhow::Result<Option<Archive>> {
+    let mut asset = match catalog::asset(collection, item, id, pool).await? {
+        Some(Value::Object(asset)) => asset,
+        _ => return Ok(None),
+    };
+
+    let version = Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string();
+
+    let key = asset
+        .get("href")
+        .and_then(Value::as_str)
+        .and_then(s3_key)
+        .map(ToOwned::to_owned);
+
+    let mut preserved = None;
+    if let Some(key) = key.filter(|key| Some(key.as_str()) == overwrites) {
+        let target = format!("{PREFIX}/{version}/{key}");
+
+        s3.client
+            .copy_object()
+            .copy_source(format!("{AWS_S3_BUCKET}/{key}"))
+            .bucket(AWS_S3_BUCKET)
+            .key(&target)
+            .acl(visibility::acl(collection, pool).await?)
+            .send()
+            .await
+            .context("Failed to archive S3 object")?;
+
+        asset.insert(
+            "href".to_string(),
+            Value::String(format!("{AWS_S3_BUCKET_BASE}/{target}")),
+        );
+        preserved = Some((key, target));
+    }
+
+    // STAC version extension fields
+    asset.insert("version".to_string(), Value::String(version.to_owned()));
+    asset.insert("deprecated".to_string(), Value::Bool(true));
+
+    Ok(Some(Archive {
+        collection: collection.to_owned(),
+        item: item.map(ToOwned::to_owned),
+        id: id.to_owned(),
+        version,
+        asset,
+        preserved,
+    }))
+}
+
+impl Archive {
+    /// Record the archived version in the asset history
+    pub(crate) async fn commit(self, pool: &PgPool) -> anyhow::Result<String> {
+        sqlx::query(
+            r#"
+            INSERT INTO meta.asset_versions (collection, item, asset, version, value)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(&self.collection)
+        .bind(self.item.unwrap_or_default())
+        .bind(&self.id)
+        .bind(&self.version)
+        .bind(Jsonb(self.asset))
+        .execute(pool)
+        .await?;
+
+        tracing::info!("archived version `{}` of asset `{}`", self.version, self.id);
+
+        Ok(self.version)
+    }
+
+    /// Restore the overwritten S3 object of a failed replacement and remove its
+    /// preserved copy
+    pub(crate) async fn rollback(self, pool: &PgPool, s3: &S3) {
+        let (key, target) = match self.preserved {
+            Some(preserved) => preserved,
+            None => return,
+        };
+
+        let restored: anyhow::Result<()> = async {
+            s3.client
+                .copy_object()
+                .copy_source(format!("{AWS_S3_BUCKET}/{target}"))
+                .bucket(AWS_S3_BUCKET)
+                .key(&key)
+                .acl(visibility::acl(&self.collection, pool).await?)
+                .send()
+                .await?;
+            s3.delete_object(AWS_S3_BUCKET, &target).await?;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = restored {
+            tracing::error!("failed to restore object `{key}` from `{target}`: {e}");
+        }
+    }
+}
+
+/// Asset path parameters
+#[derive(Deserialize)]
+pub(crate) struct AssetPath {
+    collection_id: String,
+    /// Item `id`, not set for collection assets
+    id: Option<String>,
+    asset_id: String,
+}
+
+/// Asset history
+#[derive(Serialize)]
+pub(crate) struct Versions {
+    /// Current asset followed by previous versions, latest first
+    versions: Vec<Map<String, Value>>,
+}
+
+/// List the versions of an asset
+pub(crate) async fn history(
+    Path(path): Path<AssetPath>,
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Versions>> {
+    let item = path.id.as_deref();
+
+    let mut versions: Vec<Map<String, Value>> = Vec::new();
+
+    if let Some(Value::Object(current)) =
+        catalog::asset(&path.collection_id, item, &path.asset_id, &pool).await?
+    {
+        versions.push(current);
+    }
+
+    let archived: Vec<Jsonb<Map<String, Value>>> = sqlx::query_scalar(
+        r#"
+        SELECT value FROM meta.asset_versions
+        WHERE collection = $1 AND item = $2 AND asset = $3
+        ORDER BY archived DESC
+        "#,
+    )
+    .bind(&path.collection_id)
+    .bind(item.unwrap_or_default())
+    .bind(&path.asset_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(anyhow::Error::from)?;
+
+    versions.extend(archived.into_iter().map(|asset| asset.0));
+
+    if versions.is_empty() {
+        return Err(Error::NotFound);
+    }
+
+    // Link versions with each other
+    let hrefs: Vec<Value> = versions
+        .iter()
+        .map(|asset| asset.get("href").cloned().unwrap_or_default())
+        .collect();
+
+    for (i, asset) in versions.iter_mut().enumerate() {
+        let mut links = vec![json!({ "rel": "latest-version", "href": hrefs[0] })];
+        if let Some(href) = hrefs.get(i + 1) {
+            links.push(json!({ "rel": "predecessor-version", "href": href }));
+        }
+        if i > 0 {
+            links.push(json!({ "rel": "successor-version", "href": hrefs[i - 1] }));
+        }
+        asset.insert("links".to_string(), Value::Array(links));
+    }
+
+    Ok(Json(Versions { versions }))
+}
@@ -0,0 +1,444 @@
+use std::time::Duration;
+
+use aws_sdk_s3::{model::ObjectCannedAcl, presigning::config::PresigningConfig};
+use axum::{
+    body::{boxed, Body, Full},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        response::Parts,
+        Method, Request, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use url::form_urlencoded;
+
+use ogcapi_drivers::s3::S3;
+use ogcapi_services::Error;
+use ogcapi_types::common::media_type::GEO_JSON;
+
+use crate::{
+    assets::s3_key,
+    auth::User,
+    grants::{self, process_collections},
+    AWS_S3_BUCKET,
+};
+
+/// Lifetime of signed asset links
+const EXPIRY: Duration = Duration::from_secs(3600);
+
+/// Collections with `"visibility": "private"`
+pub(crate) async fn private_collections(pool: &PgPool) -> anyhow::Result<Vec<String>> {
+    let collections = sqlx::query_scalar(
+        "SELECT id FROM meta.collections WHERE collection ->> 'visibility' = 'private'",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(collections)
+}
+
+/// Whether a collection is private, `None` if it does not exist
+async fn is_private(collection: &str, pool: &PgPool) -> anyhow::Result<Option<bool>> {
+    let private: Option<Option<bool>> = sqlx::query_scalar(
+        "SELECT collection ->> 'visibility' = 'private' FROM meta.collections WHERE id = $1",
+    )
+    .bind(collection)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(private.map(|private| private.unwrap_or_default()))
+}
+
+/// ACL of new S3 objects of a collection, private collections are not public readable.
+/// The visibility is fixed on creation, so the ACL of existing objects stays valid.
+pub(crate) async fn acl(collection: &str, pool: &PgPool) -> anyhow::Result<ObjectCannedAcl> {
+    Ok(match is_private(collection, pool).await? {
+        Some(true) => ObjectCannedAcl::Private,
+        _ => ObjectCannedAcl::PublicRead,
+    })
+}
+
+/// Collection patterns granted to the caller, if it cannot read all private collections
+async fn granted_patterns(user: Option<&User>, pool: &PgPool) -> anyhow::Result<Vec<String>> {
+    match user {
+        Some(User {
+            read_private: false,
+            grantees: Some(grantees),
+            ..
+        }) => grants::patterns(grantees, pool).await,
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Hide private collections from unauthorized callers and sign their asset links,
+/// reject visibility changes of existing collections
+pub(crate) async fn filter(req: Request<Body>, next: Next<Body>, pool: PgPool, s3: S3) -> Response {
+    let path = req.uri().path().to_owned();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = req.method().clone();
+
+    // Objects keep the ACL of the visibility they were uploaded with
+    let req = if let (&Method::PUT | &Method::PATCH, ["collections", collection]) =
+        (&method, segments.as_slice())
+    {
+        let (req, value) = match json_body(req).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let private = match is_private(collection, &pool).await {
+            Ok(Some(private)) => private,
+            Ok(None) => return next.run(req).await,
+            Err(e) => {
+                return Error::Exception(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                    .into_response()
+            }
+        };
+        let requested = match value.get("visibility") {
+            Some(visibility) => visibility.as_str() == Some("private"),
+            // Partial updates keep the visibility
+            None if method == Method::PATCH => private,
+            None => false,
+        };
+        if requested != private {
+            return Error::Exception(
+                StatusCode::CONFLICT,
+                "The visibility of a collection cannot be changed".to_string(),
+            )
+            .into_response();
+        }
+
+        req
+    } else {
+        req
+    };
+
+    filter_private(req, next, &pool, &s3).await
+}
+
+/// Hide private collections from unauthorized callers and sign their asset links
+async fn filter_private(req: Request<Body>, next: Next<Body>, pool: &PgPool, s3: &S3) -> Response {
+    let private = match private_collections(pool).await {
+        Ok(private) => private,
+        Err(e) => {
+            return Error::Exception(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                .into_response()
+        }
+    };
+    if private.is_empty() {
+        return next.run(req).await;
+    }
+
+    // Split private collections into hidden and readable ones
+    let user = req.extensions().get::<User>();
+    let read_private = matches!(
+        user,
+        Some(User {
+            read_private: true,
+            ..
+        })
+    );
+    let patterns = match granted_patterns(user, pool).await {
+        Ok(patterns) => patterns,
+        Err(e) => {
+            return Error::Exception(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                .into_response()
+        }
+    };
+    let (readable_private, hidden): (Vec<String>, Vec<String>) = private
+        .into_iter()
+        .partition(|collection| read_private || grants::granted(&patterns, collection));
+
+    let path = req.uri().path().to_owned();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let method = req.method().clone();
+
+    match (&method, segments.as_slice()) {
+        (_, ["collections", collection, ..]) if hidden.iter().any(|c| c == collection) => {
+            Error::NotFound.into_response()
+        }
+        (&Method::POST, ["processes", process, "execution"]) if !hidden.is_empty() => {
+            let (req, value) = match json_body(req).await {
+                Ok(body) => body,
+                Err(response) => return response,
+            };
+
+            if process_collections(process, &value)
+                .all
+                .iter()
+                .any(|collection| hidden.contains(collection))
+            {
+                return Error::NotFound.into_response();
+            }
+
+            next.run(req).await
+        }
+        (&Method::GET, ["collections"]) => {
+            let (parts, mut value) = match into_json(next.run(req).await).await {
+                Ok(json) => json,
+                Err(response) => return response,
+            };
+
+            if let Some(collections) = value.get_mut("collections").and_then(Value::as_array_mut) {
+                collections
+                    .retain(|c| !hidden.iter().any(|h| Some(h.as_str()) == c["id"].as_str()));
+            }
+
+            from_json(parts, value)
+        }
+        (&Method::GET, ["collections", collection, ..])
+            if readable_private.iter().any(|c| c == collection) =>
+        {
+            let (parts, mut value) = match into_json(next.run(req).await).await {
+                Ok(json) => json,
+                Err(response) => return response,
+            };
+
+            // Collection, item or item collection
+            sign(&mut value, s3).await;
+            if let Some(features) = value.get_mut("features").and_then(Value::as_array_mut) {
+                for feature in features {
+                    sign(feature, s3).await;
+                }
+            }
+
+            from_json(parts, value)
+        }
+        (&Method::GET | &Method::POST, ["search"]) => {
+            // Restrict the query, so that matched counts and paging exclude hidden items
+            let req = match restrict_search(req, &hidden, pool).await {
+                Ok(Some(req)) => req,
+                Ok(None) => return empty_search(),
+                Err(response) => return response,
+            };
+
+            let (parts, mut value) = match into_json(next.run(req).await).await {
+                Ok(json) => json,
+                Err(response) => return response,
+            };
+
+            if let Some(features) = value.get_mut("features").and_then(Value::as_array_mut) {
+                for feature in features.iter_mut() {
+                    let private = readable_private
+                        .iter()
+                        .any(|c| Some(c.as_str()) == feature["collection"].as_str());
+                    if private {
+                        sign(feature, s3).await;
+                    }
+                }
+            }
+
+            from_json(parts, value)
+        }
+        _ => next.run(req).await,
+    }
+}
+
+/// Limit the `collections` of a search to the ones which are not hidden, `None`
+/// if no collection remains. Requests not touching hidden collections are kept.
+async fn restrict_search(
+    mut req: Request<Body>,
+    hidden: &[String],
+    pool: &PgPool,
+) -> std::result::Result<Option<Request<Body>>, Response> {
+    if hidden.is_empty() {
+        return Ok(Some(req));
+    }
+
+    // `None` if the requested collections are searchable as they are
+    let searchable = |requested: Option<Vec<String>>| async move {
+        match requested {
+            Some(requested) if !requested.iter().any(|c| hidden.contains(c)) => Ok(None),
+            Some(requested) => Ok(Some(
+                requested
+                    .into_iter()
+                    .filter(|c| !hidden.contains(c))
+                    .collect::<Vec<String>>(),
+            )),
+            None => sqlx::query_scalar::<_, String>(
+                "SELECT id FROM meta.collections WHERE id <> ALL($1) ORDER BY id",
+            )
+            .bind(hidden)
+            .fetch_all(pool)
+            .await
+            .map(Some)
+            .map_err(|e| {
+                Error::Exception(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            }),
+        }
+    };
+
+    if req.method() == Method::GET {
+        let mut pairs: Vec<(String, String)> =
+            form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect();
+        let requested = pairs
+            .iter()
+            .find(|(key, _)| key == "collections")
+            .map(|(_, value)| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|c| !c.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect()
+            });
+
+        let collections = match searchable(requested).await? {
+            Some(collections) => collections,
+            None => return Ok(Some(req)),
+        };
+        if collections.is_empty() {
+            return Ok(None);
+        }
+
+        pairs.retain(|(key, _)| key != "collections");
+        pairs.push(("collections".to_string(), collections.join(",")));
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(pairs)
+            .finish();
+
+        *req.uri_mut() = match format!("{}?{query}", req.uri().path()).parse() {
+            Ok(uri) => uri,
+            Err(e) => {
+                return Err(
+                    Error::Exception(StatusCode::BAD_REQUEST, format!("{e}")).into_response()
+                )
+            }
+        };
+
+        Ok(Some(req))
+    } else {
+        let (req, mut value) = json_body(req).await?;
+        let search = match value.as_object_mut() {
+            Some(search) => search,
+            None => {
+                return Err(Error::Exception(
+                    StatusCode::BAD_REQUEST,
+                    "Invalid search request".to_string(),
+                )
+                .into_response())
+            }
+        };
+
+        let requested = search
+            .get("collections")
+            .and_then(Value::as_array)
+            .map(|collections| {
+                collections
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(ToOwned::to_owned)
+                    .collect()
+            });
+
+        let collections = match searchable(requested).await? {
+            Some(collections) => collections,
+            None => return Ok(Some(req)),
+        };
+        if collections.is_empty() {
+            return Ok(None);
+        }
+        search.insert("collections".to_string(), Value::from(collections));
+
+        let (parts, _) = req.into_parts();
+        Ok(Some(Request::from_parts(
+            parts,
+            Body::from(value.to_string()),
+        )))
+    }
+}
+
+/// Result of a search without any searchable collection
+fn empty_search() -> Response {
+    (
+        [(CONTENT_TYPE, GEO_JSON)],
+        Json(json!({
+            "type": "FeatureCollection",
+            "features": [],
+            "links": [],
+            "numberMatched": 0,
+            "numberReturned": 0
+        })),
+    )
+        .into_response()
+}
+
+/// Replace the S3 links of the assets of a collection or item with signed ones
+async fn sign(value: &mut Value, s3: &S3) {
+    let assets = match value.get_mut("assets").and_then(Value::as_object_mut) {
+        Some(assets) => assets,
+        None => return,
+    };
+
+    for asset in assets.values_mut() {
+        let key = match asset["href"].as_str().and_then(s3_key) {
+            Some(key) => key.to_owned(),
+            None => continue,
+        };
+
+        let presigned = async {
+            s3.client
+                .get_object()
+                .bucket(AWS_S3_BUCKET)
+                .key(&key)
+                .presigned(PresigningConfig::expires_in(EXPIRY)?)
+                .await
+                .map_err(anyhow::Error::from)
+        }
+        .await;
+
+        match presigned {
+            Ok(presigned) => asset["href"] = Value::String(presigned.uri().to_string()),
+            Err(e) => tracing::warn!("failed to sign `{key}`: {e}"),
+        }
+    }
+}
+
+/// Read the JSON body of a request, `null` if it is not JSON
+async fn json_body(req: Request<Body>) -> std::result::Result<(Request<Body>, Value), Response> {
+    let (mut parts, body) = req.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Err(Error::Exception(StatusCode::BAD_REQUEST, e.to_string()).into_response())
+        }
+    };
+    let value = serde_json::from_slice(&bytes).unwrap_or_default();
+
+    Ok((Request::from_parts(parts, Body::from(bytes)), value))
+}
+
+/// Split a successful JSON response into its parts and body
+async fn into_json(response: Response) -> std::result::Result<(Parts, Value), Response> {
+    if !response.status().is_success() {
+        return Err(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Err(
+                Error::Exception(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            )
+        }
+    };
+
+    match serde_json::from_slice(&bytes) {
+        Ok(value) => Ok((parts, value)),
+        Err(_) => Err(Response::from_parts(parts, boxed(Full::from(bytes)))),
+    }
+}
+
+/// Reassemble a response with a modified JSON body
+fn from_json(mut parts: Parts, value: Value) -> Response {
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(value.to_string())))
+}
@@ -0,0 +1,286 @@
+use axum::{
+    async_trait,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{stream, StreamExt};
+use geo::{BoundingRect, Contains, MultiPolygon, Point, Transform};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use url::Url;
+
+use ogcapi_services::{Error, Processor, Result, State};
+use ogcapi_types::{
+    common::Crs,
+    processes::{Execute, Process},
+};
+
+use crate::{
+    proj::Proj,
+    raster::{self, Band, Raster, TargetCrs},
+    timeseries::is_raster,
+};
+
+/// Maximum number of assets read per request
+const MAX_ASSETS: usize = 100;
+
+/// Number of assets read concurrently
+const CONCURRENCY: usize = 4;
+
+/// Zonal statistics of raster assets
+pub(crate) struct ZonalStatistics;
+
+/// Zonal statistics input schema
+#[derive(Deserialize, Debug, JsonSchema)]
+struct ZonalStatisticsInputs {
+    /// GeoJSON Polygon or MultiPolygon
+    geometry: Map<String, Value>,
+    /// CRS of the geometry
+    #[serde(default)]
+    crs: TargetCrs,
+    /// Raster items to aggregate
+    items: Vec<RasterItem>,
+    /// GRIB parameter (element) to read, e.g. `T`, the first band if not set
+    parameter: Option<String>,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+struct RasterItem {
+    /// Collection `id`
+    collection: String,
+    /// Item `id`
+    item: String,
+    /// Asset `id`, all raster assets of the item if not set
+    asset: Option<String>,
+}
+
+/// Zonal statistics output schema
+#[derive(Serialize, JsonSchema)]
+struct ZonalStatisticsOutputs {
+    /// Statistics per item and asset
+    results: Vec<ZonalResult>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ZonalResult {
+    /// Collection `id`
+    collection: String,
+    /// Item `id`
+    item: String,
+    /// Asset `id`
+    asset: String,
+    /// Statistics, missing if no valid pixel is covered
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    statistics: Option<Statistics>,
+    /// Error message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct Statistics {
+    min: f64,
+    max: f64,
+    mean: f64,
+    sum: f64,
+    /// Number of valid pixels with their center inside the geometry
+    count: usize,
+}
+
+#[async_trait]
+impl Processor for ZonalStatistics {
+    fn id(&self) -> String {
+        "zonal-statistics".to_string()
+    }
+    fn process(&self) -> Process {
+        // Config schema generation
+        let settings = SchemaSettings::default().with(|s| {
+            s.option_nullable = false;
+            s.option_add_null_type = false;
+            s.inline_subschemas = true;
+        });
+        let gen = settings.into_generator();
+
+        Process::new(
+            self.id(),
+            "0.1.0",
+            &serde_json::to_value(
+                &gen.clone()
+                    .into_root_schema_for::<ZonalStatisticsInputs>()
+                    .schema,
+            )
+            .unwrap(),
+            &serde_json::to_value(&gen.into_root_schema_for::<ZonalStatisticsOutputs>().schema)
+                .unwrap(),
+        )
+    }
+
+    async fn execute(&self, execute: Execute, state: &State, _url: &Url) -> Result<Response> {
+        let value = serde_json::to_value(execute.inputs).unwrap();
+        let inputs: ZonalStatisticsInputs = serde_json::from_value(value)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        let polygon = multi_polygon(inputs.geometry)
+            .map_err(|e| Error::Exception(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+        // Resolve assets
+        let mut assets = Vec::new();
+        for target in inputs.items {
+            let item = state
+                .drivers
+                .features
+                .read_feature(&target.collection, &target.item, &Crs::default())
+                .await?
+                .ok_or_else(|| {
+                    Error::Exception(
+                        StatusCode::NOT_FOUND,
+                        format!("Item `{}` not found", target.item),
+                    )
+                })?;
+
+            for (id, asset) in item.assets {
+                let selected = match &target.asset {
+                    Some(asset) => asset == &id,
+                    None => is_raster(&id, &asset),
+                };
+                if selected {
+                    assets.push((
+                        target.collection.to_owned(),
+                        target.item.to_owned(),
+                        id,
+                        asset,
+                    ));
+                }
+            }
+        }
+
+        if assets.len() > MAX_ASSETS {
+            return Err(Error::Exception(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Request matches {} assets, select at most {MAX_ASSETS}",
+                    assets.len()
+                ),
+            ));
+        }
+
+        // Aggregate
+        let crs = inputs.crs.code();
+        let parameter = inputs.parameter;
+        let results: Vec<ZonalResult> = stream::iter(assets)
+            .map(|(collection, item, id, asset)| {
+                let band = match &parameter {
+                    Some(parameter) => Band::Element(parameter.to_owned()),
+                    None => Band::Index(1),
+                };
+                let polygon = polygon.to_owned();
+                async move {
+                    let result = async {
+                        let bytes = raster::fetch(&asset.href, &state.s3).await?;
+                        let raster = Raster::from_bytes(bytes, band).await?;
+                        tokio::task::spawn_blocking(move || statistics(&raster, &polygon, crs))
+                            .await?
+                    }
+                    .await;
+
+                    let (statistics, message) = match result {
+                        Ok(statistics) => (statistics, None),
+                        Err(e) => {
+                            tracing::warn!("failed to aggregate `{}`: {e}", asset.href);
+                            (None, Some(e.to_string()))
+                        }
+                    };
+
+                    ZonalResult {
+                        collection,
+                        item,
+                        asset: id,
+                        statistics,
+                        message,
+                    }
+                }
+            })
+            .buffered(CONCURRENCY)
+            .collect()
+            .await;
+
+        Ok(Json(ZonalStatisticsOutputs { results }).into_response())
+    }
+}
+
+/// Parse a GeoJSON Polygon or MultiPolygon
+fn multi_polygon(geometry: Map<String, Value>) -> anyhow::Result<MultiPolygon<f64>> {
+    let geometry = geojson::Geometry::from_json_object(geometry)?;
+
+    match geo::Geometry::<f64>::try_from(geometry)? {
+        geo::Geometry::Polygon(polygon) => Ok(MultiPolygon(vec![polygon])),
+        geo::Geometry::MultiPolygon(multi_polygon) => Ok(multi_polygon),
+        _ => anyhow::bail!("Geometry must be a Polygon or MultiPolygon"),
+    }
+}
+
+/// Statistics of the valid pixels with their center inside the polygon
+fn statistics(
+    raster: &Raster,
+    polygon: &MultiPolygon<f64>,
+    crs: &str,
+) -> anyhow::Result<Option<Statistics>> {
+    if raster.projection.is_empty() {
+        anyhow::bail!("Raster has no spatial reference");
+    }
+
+    // Polygon in the raster CRS
+    let mut polygon = polygon.to_owned();
+    polygon.transform(&Proj::new(crs, &raster.projection)?.0)?;
+
+    let bbox = match polygon.bounding_rect() {
+        Some(bbox) => bbox,
+        None => return Ok(None),
+    };
+
+    // Pixel window of the bounding box (north-up rasters)
+    let gt = &raster.geo_transform;
+    let window = |a: f64, b: f64, origin: f64, size: f64, len: usize| {
+        let (a, b) = ((a - origin) / size, (b - origin) / size);
+        let start = a.min(b).floor().max(0.0) as usize;
+        let end = (a.max(b).ceil().max(0.0) as usize).min(len);
+        start..end
+    };
+    let cols = window(bbox.min().x, bbox.max().x, gt[0], gt[1], raster.width);
+    let rows = window(bbox.min().y, bbox.max().y, gt[3], gt[5], raster.height);
+
+    let (mut min, mut max, mut sum, mut count) = (f64::INFINITY, f64::NEG_INFINITY, 0.0, 0);
+    for row in rows {
+        for col in cols.clone() {
+            let value = raster.data[row * raster.width + col];
+            if !raster.is_valid(value) {
+                continue;
+            }
+
+            let center = Point::new(
+                gt[0] + (col as f64 + 0.5) * gt[1],
+                gt[3] + (row as f64 + 0.5) * gt[5],
+            );
+            if polygon.contains(&center) {
+                min = min.min(value);
+                max = max.max(value);
+                sum += value;
+                count += 1;
+            }
+        }
+    }
+
+    if count == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(Statistics {
+        min,
+        max,
+        mean: sum / count as f64,
+        sum,
+        count,
+    }))
+}
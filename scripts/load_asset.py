@@ -1,11 +1,18 @@
 import base64
 import json
+import os
 from pathlib import Path
 import requests
 
 # Creadentials (to be replaced)
 auth = ("user", "password")
 
+# API key with the `assets:write` scope, preferred over basic credentials
+headers = {}
+if os.environ.get("API_KEY"):
+    auth = None
+    headers = {"Authorization": f"Bearer {os.environ['API_KEY']}"}
+
 # API endpoint
 url = "https://poc.meteoschweiz-poc.swisstopo.cloud/root"
 # url = "http://0.0.0.0:8484/root"
@@ -51,5 +58,7 @@ with path.open("rb") as file:
         }
     }
 
-    r = requests.post(f"{url}/processes/load-asset/execution", auth=auth, json=inputs)
+    r = requests.post(
+        f"{url}/processes/load-asset/execution", auth=auth, headers=headers, json=inputs
+    )
     print(r.text)
@@ -45,8 +45,15 @@ body="{
 ROOT="https://poc.meteoschweiz-poc.swisstopo.cloud/root"
 # ROOT="http://0.0.0.0:8484/root"
 
+# Prefer an API key with the `assets:write` scope over basic credentials
+if [ -n "$API_KEY" ]; then
+    AUTH=(-H "Authorization: Bearer $API_KEY")
+else
+    AUTH=(-u "user:password")
+fi
+
 curl -X POST "$ROOT/processes/load-asset/execution" \
-    -u "user:password" \
+    "${AUTH[@]}" \
     -H "Content-Type: application/json" \
     -d @- "$HOST" <<CURL_DATA
 $body